@@ -0,0 +1,148 @@
+//! Simulates a game using the library: a few frames of a game loop fed with
+//! fake platform events
+
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+use macroquad_ub_test::audio::{
+    is_sound_playing, load_sound_from_bytes, play_sound_once, take_audio_mixer,
+};
+use macroquad_ub_test::gamepad::{
+    gamepad_axis, gamepad_axis_event, gamepad_button_down_event, gamepad_button_up_event,
+    gamepad_connected_event, GamepadAxis, GamepadButton, GamepadId,
+};
+use macroquad_ub_test::input::{
+    is_mouse_button_pressed, mouse_position, register_input_subscriber, MouseButton, TouchPhase,
+};
+use macroquad_ub_test::window::{
+    get_internal_gl, take_gl_token, with_internal_gl, InternalGlContext,
+};
+use macroquad_ub_test::{
+    begin_frame, end_frame, get_context, init_context, mouse_motion_event, reset_context,
+    resize_event, shutdown_context, touch_event, ContextError,
+};
+
+/// Takes the caller's handle: it has no token to call `with_internal_gl()`
+/// with, so it cannot create a second handle aliasing this one.
+fn helper(gl: &mut InternalGlContext) {
+    *gl.quad_gl() += 1;
+}
+
+/// A few samples of a square wave, as an 8-bit mono WAV file
+fn beep_wav() -> Vec<u8> {
+    let samples = [255, 255, 0, 0, 255, 255, 0, 0];
+    let mut wav = vec![];
+    wav.extend_from_slice(b"RIFF");
+    wav.extend_from_slice(&(36 + samples.len() as u32).to_le_bytes());
+    wav.extend_from_slice(b"WAVEfmt ");
+    // size, PCM, mono, 44100 Hz, 44100 bytes/s, 1 byte per frame, 8 bits
+    wav.extend_from_slice(&16u32.to_le_bytes());
+    wav.extend_from_slice(&[1, 0, 1, 0]);
+    wav.extend_from_slice(&44100u32.to_le_bytes());
+    wav.extend_from_slice(&44100u32.to_le_bytes());
+    wav.extend_from_slice(&[1, 0, 8, 0]);
+    wav.extend_from_slice(b"data");
+    wav.extend_from_slice(&(samples.len() as u32).to_le_bytes());
+    wav.extend_from_slice(&samples);
+    wav
+}
+
+fn main() {
+    // Simulates Window::from_config()
+    unsafe {
+        // must be called before `get_context()`
+        init_context().expect("Context initialized twice");
+    }
+
+    // The main loop owns the only token for the graphics state
+    let mut gl_token = take_gl_token().expect("gl token taken twice");
+
+    // Both get every input event
+    let ui_events = register_input_subscriber();
+    let gameplay_events = register_input_subscriber();
+
+    // Stands in for the platform audio callback: the mixer never touches
+    // the Context, so it can run on its own thread
+    let mut mixer = take_audio_mixer().expect("mixer taken twice");
+    let audio_running = Arc::new(AtomicBool::new(true));
+    let audio_thread = thread::spawn({
+        let audio_running = audio_running.clone();
+        move || {
+            let mut peak = 0.0f32;
+            let mut samples = [0.0; 16];
+            while audio_running.load(Ordering::Relaxed) {
+                mixer.mix(&mut samples);
+                peak = samples
+                    .iter()
+                    .fold(peak, |peak, sample| peak.max(sample.abs()));
+                thread::sleep(Duration::from_millis(1));
+            }
+            peak
+        }
+    });
+
+    let beep = load_sound_from_bytes(&beep_wav()).expect("beep is a valid WAV");
+    let pad = GamepadId(0);
+    gamepad_connected_event(pad);
+
+    // Simulate game loop
+    for frame in 0..5 {
+        begin_frame();
+
+        with_internal_gl(&mut gl_token, |mut gl| {
+            gl.flush();
+            *gl.quad_context() += 1;
+            helper(&mut gl);
+        });
+
+        resize_event(1920., 1080.);
+        mouse_motion_event(42.0, 84.0);
+        touch_event(TouchPhase::Started, 0, frame as f32, frame as f32);
+        touch_event(TouchPhase::Ended, 0, frame as f32, frame as f32);
+        gamepad_button_down_event(pad, GamepadButton::South);
+        gamepad_button_up_event(pad, GamepadButton::South);
+        gamepad_axis_event(pad, GamepadAxis::LeftStickX, frame as f32 / 4.0);
+        play_sound_once(beep);
+        with_internal_gl(&mut gl_token, |mut gl| gl.flush());
+
+        let ui = ui_events.drain();
+        assert_eq!(ui, gameplay_events.drain());
+        println!(
+            "frame {}: {} input events, mouse at {:?}, left stick x {}",
+            frame,
+            ui.len(),
+            mouse_position(),
+            gamepad_axis(pad, GamepadAxis::LeftStickX),
+        );
+
+        // the touch is simulated as a click
+        assert!(is_mouse_button_pressed(MouseButton::Left));
+        let report = end_frame();
+        assert_eq!(report.touches_pruned, 1);
+        assert_eq!(report.gamepad_buttons_cleared, 2);
+    }
+    unsafe {
+        dbg!(&*get_context());
+    }
+    // the mixer reports the beeps ending back to the main thread
+    while is_sound_playing(beep) {
+        thread::sleep(Duration::from_millis(1));
+    }
+    audio_running.store(false, Ordering::Relaxed);
+    let peak = audio_thread.join().expect("audio thread panicked");
+    println!("audio peak {}", peak);
+
+    // Simulates restarting the window: handles into the old Context are
+    // detected instead of dangling
+    let mut gl = unsafe { get_internal_gl() };
+    unsafe {
+        reset_context().expect("Context is borrowed");
+    }
+    assert_eq!(gl.try_flush(), Err(ContextError::StaleHandle));
+
+    unsafe {
+        shutdown_context().expect("Context is borrowed");
+    }
+}
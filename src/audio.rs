@@ -0,0 +1,578 @@
+//! Sound loading and a software mixer playing the loaded sounds.
+//!
+//! The functions of this module run on the main thread and only send
+//! commands to the [Mixer], which the platform audio callback takes with
+//! [take_audio_mixer()] and runs on its own thread.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+use std::sync::mpsc::{channel, Receiver, Sender};
+
+use crate::{with_context, with_context_ref};
+use mixer::MixerCommand;
+
+mod decode;
+mod mixer;
+mod resample;
+
+pub use mixer::{Mixer, VOICES};
+
+/// Sample rate of the mixer output, in Hz
+pub const SAMPLE_RATE: u32 = 44100;
+
+/// The mixer output is interleaved stereo: left, right, left, ...
+pub const OUTPUT_CHANNELS: usize = 2;
+
+/// Handle to a sound loaded with [load_sound_from_bytes()], only valid for
+/// the `Context` it was loaded into
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct Sound(u64);
+
+/// Handle to a sound playing on one of the [VOICES], returned by
+/// [play_sound()]. Stays valid, but without effect, once the sound ends.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct Voice(u64);
+
+/// See [play_sound()]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PlaySoundParams {
+    /// Start over when the end is reached, until stopped
+    pub looped: bool,
+    pub volume: f32,
+    /// From `-1.0` (left only) to `1.0` (right only)
+    pub pan: f32,
+    /// Playback speed, `2.0` being an octave higher
+    pub pitch: f32,
+    /// Seconds to get from silence to `volume`
+    pub fade_in: f32,
+}
+
+impl Default for PlaySoundParams {
+    fn default() -> Self {
+        Self {
+            looped: false,
+            volume: 1.0,
+            pan: 0.0,
+            pitch: 1.0,
+            fade_in: 0.0,
+        }
+    }
+}
+
+/// How [load_sound_from_bytes()] converts sounds to [SAMPLE_RATE], see
+/// [set_resample_quality()]. Sounds already at that rate are not changed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ResampleQuality {
+    /// Straight lines between samples, fastest but muffles and aliases
+    Linear,
+    /// Catmull-Rom spline through the neighbouring samples
+    #[default]
+    Cubic,
+    /// Band-limited interpolation, slowest to load but without audible
+    /// artifacts
+    WindowedSinc,
+}
+
+/// Error returned by [load_sound_from_bytes()]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SoundError {
+    /// The data is neither RIFF/WAVE nor Ogg
+    UnknownFormat,
+    /// The file is truncated or inconsistent
+    Malformed(String),
+    /// The file is valid but uses an encoding that is not decoded, like
+    /// compressed WAV or Ogg Opus
+    Unsupported(String),
+}
+
+impl fmt::Display for SoundError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SoundError::UnknownFormat => write!(f, "sound is neither WAV nor Ogg Vorbis"),
+            SoundError::Malformed(reason) => write!(f, "malformed sound file: {}", reason),
+            SoundError::Unsupported(reason) => write!(f, "unsupported sound file: {}", reason),
+        }
+    }
+}
+
+impl std::error::Error for SoundError {}
+
+/// Decoded samples of a loaded sound
+pub(crate) struct SoundData {
+    pub(crate) sample_rate: u32,
+    pub(crate) channels: u16,
+    /// Interleaved, nominally in `-1.0..=1.0`
+    pub(crate) samples: Vec<f32>,
+}
+
+impl SoundData {
+    pub(crate) fn frames(&self) -> usize {
+        self.samples.len() / self.channels as usize
+    }
+
+    /// Sample of `channel` of the output. Loaded sounds are already mixed to
+    /// [OUTPUT_CHANNELS], mono is still played on all channels.
+    fn output_sample(&self, frame: usize, channel: usize) -> f32 {
+        let channels = self.channels as usize;
+        self.samples[frame * channels + channel.min(channels - 1)]
+    }
+}
+
+// The samples would flood `dbg!(get_context())`
+impl fmt::Debug for SoundData {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("SoundData")
+            .field("sample_rate", &self.sample_rate)
+            .field("channels", &self.channels)
+            .field("frames", &self.frames())
+            .finish()
+    }
+}
+
+/// The main thread side of the audio
+#[derive(Debug)]
+pub(crate) struct AudioContext {
+    next_sound: u64,
+    next_voice: u64,
+    resample_quality: ResampleQuality,
+    /// Loaded sounds, their samples are in the mixer
+    sounds: BTreeSet<Sound>,
+    /// Voices that may be playing, with their sound: the ones the mixer did
+    /// not report as ended yet
+    voices: BTreeMap<Voice, Sound>,
+    commands: Sender<MixerCommand>,
+    ended: Receiver<Voice>,
+    /// Samples of unloaded sounds, to free them on this thread
+    removed: Receiver<SoundData>,
+    /// Until taken by [take_audio_mixer()], commands are applied to it
+    /// right away instead of piling up in the channel
+    mixer: Option<Mixer>,
+}
+
+impl Default for AudioContext {
+    fn default() -> Self {
+        let (commands, receiver) = channel();
+        let (sender, ended) = channel();
+        let (removed_sender, removed) = channel();
+        AudioContext {
+            next_sound: 0,
+            next_voice: 0,
+            resample_quality: ResampleQuality::default(),
+            sounds: BTreeSet::new(),
+            voices: BTreeMap::new(),
+            commands,
+            ended,
+            removed,
+            mixer: Some(Mixer::new(receiver, sender, removed_sender)),
+        }
+    }
+}
+
+impl AudioContext {
+    /// Returns whether the command reached a taken mixer: it fails once
+    /// the audio thread dropped the mixer, nothing plays then
+    fn send(&mut self, command: MixerCommand) -> bool {
+        let sent = match &mut self.mixer {
+            Some(mixer) => {
+                mixer.apply(command);
+                false
+            }
+            None => self.commands.send(command).is_ok(),
+        };
+        // drops the samples of the sounds the mixer removed so far
+        while self.removed.try_recv().is_ok() {}
+        sent
+    }
+
+    /// Forget the voices the mixer reported as ended
+    fn update_voices(&mut self) {
+        while let Ok(voice) = self.ended.try_recv() {
+            self.voices.remove(&voice);
+        }
+    }
+
+    pub(crate) fn add_sound(&mut self, data: SoundData) -> Sound {
+        let sound = Sound(self.next_sound);
+        self.next_sound += 1;
+        self.sounds.insert(sound);
+        self.send(MixerCommand::AddSound { sound, data });
+        sound
+    }
+
+    /// The mixer applies the commands in order and frees a voice before
+    /// reporting it, so it has a free voice whenever `voices` is not full.
+    /// Voices are only started on a taken mixer, which ends them.
+    fn play(&mut self, sound: Sound, params: PlaySoundParams) -> Option<Voice> {
+        self.update_voices();
+        if !self.sounds.contains(&sound) || self.voices.len() == VOICES || self.mixer.is_some() {
+            return None;
+        }
+        let voice = Voice(self.next_voice);
+        let sent = self.send(MixerCommand::Play {
+            voice,
+            sound,
+            params,
+        });
+        if !sent {
+            return None;
+        }
+        self.next_voice += 1;
+        self.voices.insert(voice, sound);
+        Some(voice)
+    }
+
+    fn stop_sound(&mut self, sound: Sound) {
+        self.voices.retain(|_, playing| *playing != sound);
+        self.send(MixerCommand::StopSound { sound });
+    }
+}
+
+/// Load a RIFF/WAVE (PCM of 8, 16, 24 or 32 bits, or float) or Ogg Vorbis
+/// file.
+///
+/// The samples are resampled to [SAMPLE_RATE] with the
+/// [resample_quality()], and mixed to [OUTPUT_CHANNELS]: mono is played on
+/// both sides and channels after the first two are centered. Decoding and
+/// conversion happen on the calling thread, the mixer only receives the
+/// converted samples.
+///
+/// Sample rates outside of 8000 to 192000 Hz, and sounds lasting more than
+/// an hour, fail with [SoundError::Unsupported].
+///
+/// # Panics
+/// On any [ContextError](crate::ContextError)
+#[track_caller]
+pub fn load_sound_from_bytes(data: &[u8]) -> Result<Sound, SoundError> {
+    let quality = resample_quality();
+    let data = resample::convert(decode::decode(data)?, quality)?;
+    Ok(with_context(|context| {
+        context.audio_context.add_sound(data)
+    }))
+}
+
+/// Quality of the conversion of the sounds loaded from now on to
+/// [SAMPLE_RATE]. [ResampleQuality::Cubic] by default.
+///
+/// # Panics
+/// On any [ContextError](crate::ContextError)
+#[track_caller]
+pub fn set_resample_quality(quality: ResampleQuality) {
+    with_context(|context| context.audio_context.resample_quality = quality);
+}
+
+/// See [set_resample_quality()]
+///
+/// # Panics
+/// On any [ContextError](crate::ContextError)
+#[track_caller]
+pub fn resample_quality() -> ResampleQuality {
+    with_context_ref(|context| context.audio_context.resample_quality)
+}
+
+/// Hand the [Mixer] over to the platform audio callback, which can run it on
+/// its own thread. Returns `None` if it was already taken.
+///
+/// Sounds can be loaded before, but [play_sound()] fails until the mixer is
+/// taken, and nothing plays until [Mixer::mix()] is called. The mixer stops
+/// playing once the `Context` is shut down or reset.
+///
+/// # Panics
+/// On any [ContextError](crate::ContextError)
+#[track_caller]
+pub fn take_audio_mixer() -> Option<Mixer> {
+    with_context(|context| context.audio_context.mixer.take())
+}
+
+/// Send `command` to the mixer
+#[track_caller]
+fn mixer_command(command: MixerCommand) {
+    with_context(|context| context.audio_context.send(command));
+}
+
+/// Stop and free `sound`, the handle must not be used anymore.
+///
+/// # Panics
+/// On any [ContextError](crate::ContextError)
+#[track_caller]
+pub fn unload_sound(sound: Sound) {
+    with_context(|context| {
+        let audio_context = &mut context.audio_context;
+        audio_context.sounds.remove(&sound);
+        audio_context.stop_sound(sound);
+        audio_context.send(MixerCommand::RemoveSound { sound });
+    });
+}
+
+/// Start playing `sound` on a free voice, on top of the voices already
+/// playing it.
+///
+/// Returns `None`, without playing anything, if `sound` is not loaded, all
+/// [VOICES] are busy, or no mixer is running: it was not taken with
+/// [take_audio_mixer()] yet, or was dropped. A voice is busy until the mixer
+/// reports it ended, so a voice ending during the current [Mixer::mix()] is
+/// still busy.
+///
+/// # Panics
+/// On any [ContextError](crate::ContextError)
+#[track_caller]
+pub fn play_sound(sound: Sound, params: PlaySoundParams) -> Option<Voice> {
+    with_context(|context| context.audio_context.play(sound, params))
+}
+
+/// [play_sound()] with the default [PlaySoundParams]
+///
+/// # Panics
+/// On any [ContextError](crate::ContextError)
+#[track_caller]
+pub fn play_sound_once(sound: Sound) {
+    play_sound(sound, PlaySoundParams::default());
+}
+
+/// Stop all voices playing `sound`.
+///
+/// # Panics
+/// On any [ContextError](crate::ContextError)
+#[track_caller]
+pub fn stop_sound(sound: Sound) {
+    with_context(|context| context.audio_context.stop_sound(sound));
+}
+
+/// Change the volume of all voices playing `sound`.
+///
+/// # Panics
+/// On any [ContextError](crate::ContextError)
+#[track_caller]
+pub fn set_sound_volume(sound: Sound, volume: f32) {
+    mixer_command(MixerCommand::SetSoundVolume { sound, volume });
+}
+
+/// Whether a voice playing `sound` has not been reported as ended by the
+/// mixer yet.
+///
+/// # Panics
+/// On any [ContextError](crate::ContextError)
+#[track_caller]
+pub fn is_sound_playing(sound: Sound) -> bool {
+    with_context(|context| {
+        let audio_context = &mut context.audio_context;
+        audio_context.update_voices();
+        audio_context
+            .voices
+            .values()
+            .any(|&playing| playing == sound)
+    })
+}
+
+/// # Panics
+/// On any [ContextError](crate::ContextError)
+#[track_caller]
+pub fn stop_voice(voice: Voice) {
+    with_context(|context| {
+        context.audio_context.voices.remove(&voice);
+        context
+            .audio_context
+            .send(MixerCommand::StopVoice { voice });
+    });
+}
+
+/// Fade `voice` to silence over `duration` seconds, then stop it.
+///
+/// # Panics
+/// On any [ContextError](crate::ContextError)
+#[track_caller]
+pub fn fade_out_voice(voice: Voice, duration: f32) {
+    mixer_command(MixerCommand::FadeOutVoice { voice, duration });
+}
+
+/// See [PlaySoundParams::volume].
+///
+/// # Panics
+/// On any [ContextError](crate::ContextError)
+#[track_caller]
+pub fn set_voice_volume(voice: Voice, volume: f32) {
+    mixer_command(MixerCommand::SetVoiceVolume { voice, volume });
+}
+
+/// See [PlaySoundParams::pan].
+///
+/// # Panics
+/// On any [ContextError](crate::ContextError)
+#[track_caller]
+pub fn set_voice_pan(voice: Voice, pan: f32) {
+    mixer_command(MixerCommand::SetVoicePan { voice, pan });
+}
+
+/// See [PlaySoundParams::pitch].
+///
+/// # Panics
+/// On any [ContextError](crate::ContextError)
+#[track_caller]
+pub fn set_voice_pitch(voice: Voice, pitch: f32) {
+    mixer_command(MixerCommand::SetVoicePitch { voice, pitch });
+}
+
+/// Whether `voice` has been neither stopped nor reported as ended by the
+/// mixer.
+///
+/// # Panics
+/// On any [ContextError](crate::ContextError)
+#[track_caller]
+pub fn is_voice_playing(voice: Voice) -> bool {
+    with_context(|context| {
+        let audio_context = &mut context.audio_context;
+        audio_context.update_voices();
+        audio_context.voices.contains_key(&voice)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::{fresh_context, wav};
+
+    /// Loads unsigned 8-bit samples
+    fn load_pcm_u8(channels: u16, data: &[u8]) -> Sound {
+        load_sound_from_bytes(&wav(1, channels, SAMPLE_RATE, 8, data)).unwrap()
+    }
+
+    #[test]
+    fn play_stop_and_volume() {
+        let _context = fresh_context();
+        let mut mixer = take_audio_mixer().unwrap();
+        let sound = load_pcm_u8(1, &[192, 64]);
+        let mut buffer = [0.0; 6];
+
+        play_sound(
+            sound,
+            PlaySoundParams {
+                looped: true,
+                ..PlaySoundParams::default()
+            },
+        );
+        mixer.mix(&mut buffer);
+        assert_eq!(buffer, [0.5, 0.5, -0.5, -0.5, 0.5, 0.5]);
+
+        set_sound_volume(sound, 0.5);
+        mixer.mix(&mut buffer);
+        assert_eq!(buffer, [-0.25, -0.25, 0.25, 0.25, -0.25, -0.25]);
+
+        stop_sound(sound);
+        assert!(!is_sound_playing(sound));
+        mixer.mix(&mut buffer);
+        assert_eq!(buffer, [0.0; 6]);
+    }
+
+    #[test]
+    fn sounds_add_up_and_end() {
+        let _context = fresh_context();
+        let mut mixer = take_audio_mixer().unwrap();
+        let long = load_pcm_u8(1, &[192, 192, 192]);
+        let short = load_pcm_u8(1, &[255]);
+        play_sound_once(long);
+        play_sound_once(short);
+
+        let mut buffer = [0.0; 8];
+        mixer.mix(&mut buffer);
+        // clipped, then only the long sound, then nothing
+        assert_eq!(buffer, [1.0, 1.0, 0.5, 0.5, 0.5, 0.5, 0.0, 0.0]);
+        assert!(!is_sound_playing(long));
+        assert!(!is_sound_playing(short));
+
+        unload_sound(long);
+        play_sound_once(long);
+        assert!(!is_sound_playing(long));
+    }
+
+    #[test]
+    fn stereo_and_errors() {
+        let _context = fresh_context();
+        let mut mixer = take_audio_mixer().unwrap();
+        assert!(take_audio_mixer().is_none());
+        let sound = load_pcm_u8(2, &[192, 64]);
+        play_sound_once(sound);
+        let mut buffer = [0.0; 4];
+        mixer.mix(&mut buffer);
+        assert_eq!(buffer, [0.5, -0.5, 0.0, 0.0]);
+
+        assert_eq!(
+            load_sound_from_bytes(&[128; 16]),
+            Err(SoundError::UnknownFormat)
+        );
+    }
+
+    #[test]
+    fn voices() {
+        let _context = fresh_context();
+        let mut mixer = take_audio_mixer().unwrap();
+        let sound = load_pcm_u8(1, &[192; 4]);
+        let mut buffer = [0.0; 2];
+
+        let voice = play_sound(
+            sound,
+            PlaySoundParams {
+                looped: true,
+                pan: 1.0,
+                ..PlaySoundParams::default()
+            },
+        )
+        .unwrap();
+        mixer.mix(&mut buffer);
+        assert_eq!(buffer, [0.0, 0.5]);
+        set_voice_pan(voice, -0.5);
+        set_voice_volume(voice, 2.0);
+        mixer.mix(&mut buffer);
+        assert_eq!(buffer, [1.0, 0.5]);
+
+        let others: Vec<_> = (1..VOICES)
+            .map(|_| play_sound(sound, PlaySoundParams::default()))
+            .collect();
+        assert!(others.iter().all(Option::is_some));
+        assert_eq!(play_sound(sound, PlaySoundParams::default()), None);
+        stop_sound(sound);
+
+        let voice = play_sound(sound, PlaySoundParams::default()).unwrap();
+        fade_out_voice(voice, 0.0);
+        assert!(is_voice_playing(voice));
+        mixer.mix(&mut buffer);
+        assert!(!is_voice_playing(voice));
+    }
+
+    /// Nothing piles up for a mixer that is not running: sounds loaded
+    /// before it is taken are handed over with it, plays are refused
+    #[test]
+    fn play_needs_a_running_mixer() {
+        let _context = fresh_context();
+        let sound = load_pcm_u8(1, &[192]);
+        let unloaded = load_pcm_u8(1, &[64]);
+        unload_sound(unloaded);
+        assert_eq!(play_sound(sound, PlaySoundParams::default()), None);
+        assert!(!is_sound_playing(sound));
+
+        let mut mixer = take_audio_mixer().unwrap();
+        play_sound_once(sound);
+        play_sound_once(unloaded);
+        let mut buffer = [0.0; 2];
+        mixer.mix(&mut buffer);
+        assert_eq!(buffer, [0.5, 0.5]);
+
+        drop(mixer);
+        assert_eq!(play_sound(sound, PlaySoundParams::default()), None);
+    }
+
+    #[test]
+    fn converted_on_load() {
+        let _context = fresh_context();
+        let mut mixer = take_audio_mixer().unwrap();
+        assert_eq!(resample_quality(), ResampleQuality::Cubic);
+        set_resample_quality(ResampleQuality::Linear);
+        let sound = load_sound_from_bytes(&wav(1, 1, SAMPLE_RATE / 2, 8, &[192, 64])).unwrap();
+        play_sound_once(sound);
+
+        let mut buffer = [0.0; 10];
+        mixer.mix(&mut buffer);
+        assert_eq!(
+            buffer,
+            [0.5, 0.5, 0.0, 0.0, -0.5, -0.5, -0.5, -0.5, 0.0, 0.0]
+        );
+    }
+}
@@ -0,0 +1,306 @@
+//! Decoding of RIFF/WAVE and Ogg Vorbis files into [SoundData]
+
+use std::io::Cursor;
+use std::ops::RangeInclusive;
+
+use lewton::header::HeaderReadError;
+use lewton::inside_ogg::OggStreamReader;
+use lewton::samples::InterleavedSamples;
+use lewton::VorbisError;
+
+use super::{SoundData, SoundError};
+
+const WAVE_FORMAT_PCM: u16 = 1;
+const WAVE_FORMAT_IEEE_FLOAT: u16 = 3;
+/// The actual format is in the first two bytes of the sub-format GUID
+const WAVE_FORMAT_EXTENSIBLE: u16 = 0xfffe;
+
+/// Sample rates of the sounds decoded, in Hz. Resampling much slower sounds
+/// to [SAMPLE_RATE](super::SAMPLE_RATE) would multiply their size.
+const SAMPLE_RATES: RangeInclusive<u32> = 8000..=192000;
+
+/// Detect the format of `data` and decode it
+pub(crate) fn decode(data: &[u8]) -> Result<SoundData, SoundError> {
+    let sound = if data.len() >= 12 && &data[0..4] == b"RIFF" && &data[8..12] == b"WAVE" {
+        decode_wav(&data[12..])?
+    } else if data.starts_with(b"OggS") {
+        decode_ogg(data)?
+    } else {
+        return Err(SoundError::UnknownFormat);
+    };
+    if !SAMPLE_RATES.contains(&sound.sample_rate) {
+        return Err(SoundError::Unsupported(format!(
+            "sample rate of {} Hz, outside of {} to {} Hz",
+            sound.sample_rate,
+            SAMPLE_RATES.start(),
+            SAMPLE_RATES.end()
+        )));
+    }
+    Ok(sound)
+}
+
+/// Little-endian reads, failing with [SoundError::Malformed] past the end
+struct Reader<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    fn bytes(&mut self, len: usize, what: &str) -> Result<&'a [u8], SoundError> {
+        if self.data.len() < len {
+            return Err(SoundError::Malformed(format!("truncated {}", what)));
+        }
+        let (bytes, rest) = self.data.split_at(len);
+        self.data = rest;
+        Ok(bytes)
+    }
+
+    fn u16(&mut self, what: &str) -> Result<u16, SoundError> {
+        let bytes = self.bytes(2, what)?;
+        Ok(u16::from_le_bytes([bytes[0], bytes[1]]))
+    }
+
+    fn u32(&mut self, what: &str) -> Result<u32, SoundError> {
+        let bytes = self.bytes(4, what)?;
+        Ok(u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+struct WavFormat {
+    tag: u16,
+    channels: u16,
+    sample_rate: u32,
+    block_align: u16,
+    bits_per_sample: u16,
+}
+
+fn parse_wav_format(chunk: &[u8]) -> Result<WavFormat, SoundError> {
+    let mut reader = Reader { data: chunk };
+    let mut format = WavFormat {
+        tag: reader.u16("fmt chunk")?,
+        channels: reader.u16("fmt chunk")?,
+        sample_rate: reader.u32("fmt chunk")?,
+        block_align: {
+            let _byte_rate = reader.u32("fmt chunk")?;
+            reader.u16("fmt chunk")?
+        },
+        bits_per_sample: reader.u16("fmt chunk")?,
+    };
+    if format.tag == WAVE_FORMAT_EXTENSIBLE {
+        let _extension_size = reader.u16("fmt extension")?;
+        let _valid_bits = reader.u16("fmt extension")?;
+        let _channel_mask = reader.u32("fmt extension")?;
+        format.tag = reader.u16("fmt extension")?;
+    }
+    if format.channels == 0 || format.sample_rate == 0 {
+        return Err(SoundError::Malformed(format!(
+            "{} channels at {} Hz",
+            format.channels, format.sample_rate
+        )));
+    }
+    Ok(format)
+}
+
+/// `data` starts after the `WAVE` tag
+fn decode_wav(data: &[u8]) -> Result<SoundData, SoundError> {
+    let mut reader = Reader { data };
+    let mut format = None;
+    let samples = loop {
+        if reader.data.is_empty() {
+            return Err(SoundError::Malformed("no data chunk".to_string()));
+        }
+        let id = reader.bytes(4, "chunk header")?;
+        let size = reader.u32("chunk header")? as usize;
+        let chunk = reader.bytes(size, "chunk")?;
+        // chunks are padded to an even size
+        if size % 2 == 1 && !reader.data.is_empty() {
+            reader.bytes(1, "chunk padding")?;
+        }
+        match id {
+            b"fmt " => format = Some(parse_wav_format(chunk)?),
+            b"data" => {
+                let format = format
+                    .ok_or_else(|| SoundError::Malformed("data chunk before fmt chunk".into()))?;
+                break decode_wav_samples(format, chunk)?;
+            }
+            _ => {}
+        }
+    };
+    let format = format.unwrap();
+    Ok(SoundData {
+        sample_rate: format.sample_rate,
+        channels: format.channels,
+        samples,
+    })
+}
+
+/// Decodes one sample, given exactly its bytes
+type SampleDecoder = fn(&[u8]) -> f32;
+
+fn sample_decoder(format: WavFormat) -> Option<SampleDecoder> {
+    Some(match (format.tag, format.bits_per_sample) {
+        (WAVE_FORMAT_PCM, 8) => |sample| (sample[0] as f32 - 128.0) / 128.0,
+        (WAVE_FORMAT_PCM, 16) => {
+            |sample| i16::from_le_bytes([sample[0], sample[1]]) as f32 / 32768.0
+        }
+        // shifted into the top of an i32 to keep the sign
+        (WAVE_FORMAT_PCM, 24) => {
+            |sample| i32::from_le_bytes([0, sample[0], sample[1], sample[2]]) as f32 / 2147483648.0
+        }
+        (WAVE_FORMAT_PCM, 32) => |sample| {
+            i32::from_le_bytes([sample[0], sample[1], sample[2], sample[3]]) as f32 / 2147483648.0
+        },
+        (WAVE_FORMAT_IEEE_FLOAT, 32) => {
+            |sample| f32::from_le_bytes([sample[0], sample[1], sample[2], sample[3]])
+        }
+        (WAVE_FORMAT_IEEE_FLOAT, 64) => |sample| {
+            let mut bytes = [0; 8];
+            bytes.copy_from_slice(sample);
+            f64::from_le_bytes(bytes) as f32
+        },
+        _ => return None,
+    })
+}
+
+fn decode_wav_samples(format: WavFormat, data: &[u8]) -> Result<Vec<f32>, SoundError> {
+    let decoder = sample_decoder(format).ok_or_else(|| {
+        SoundError::Unsupported(format!(
+            "WAV format {:#x} with {} bits per sample",
+            format.tag, format.bits_per_sample
+        ))
+    })?;
+    let bytes_per_sample = (format.bits_per_sample / 8) as usize;
+    if format.block_align as usize != bytes_per_sample * format.channels as usize {
+        return Err(SoundError::Malformed(format!(
+            "block align of {} bytes for {} channels of {} bits",
+            format.block_align, format.channels, format.bits_per_sample
+        )));
+    }
+    // a trailing partial frame is dropped
+    let frames = data.len() / format.block_align as usize;
+    Ok(data[..frames * format.block_align as usize]
+        .chunks_exact(bytes_per_sample)
+        .map(decoder)
+        .collect())
+}
+
+fn vorbis_error(err: VorbisError) -> SoundError {
+    match err {
+        VorbisError::BadHeader(HeaderReadError::NotVorbisHeader) => {
+            SoundError::Unsupported("Ogg stream is not Vorbis".to_string())
+        }
+        VorbisError::BadHeader(HeaderReadError::UnsupportedVorbisVersion) => {
+            SoundError::Unsupported("Vorbis version".to_string())
+        }
+        err => SoundError::Malformed(err.to_string()),
+    }
+}
+
+fn decode_ogg(data: &[u8]) -> Result<SoundData, SoundError> {
+    let mut reader = OggStreamReader::new(Cursor::new(data)).map_err(vorbis_error)?;
+    let mut samples = vec![];
+    while let Some(packet) = reader
+        .read_dec_packet_generic::<InterleavedSamples<f32>>()
+        .map_err(vorbis_error)?
+    {
+        samples.extend_from_slice(&packet.samples);
+    }
+    Ok(SoundData {
+        sample_rate: reader.ident_hdr.audio_sample_rate,
+        channels: reader.ident_hdr.audio_channels.into(),
+        samples,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::wav;
+
+    #[test]
+    fn wav_formats() {
+        let sound = decode(&wav(WAVE_FORMAT_PCM, 1, 8000, 8, &[0, 128, 192])).unwrap();
+        assert_eq!((sound.sample_rate, sound.channels), (8000, 1));
+        assert_eq!(sound.samples, [-1.0, 0.0, 0.5]);
+
+        let data = [0x00, 0x80, 0x00, 0x40];
+        let sound = decode(&wav(WAVE_FORMAT_PCM, 2, 44100, 16, &data)).unwrap();
+        assert_eq!(sound.channels, 2);
+        assert_eq!(sound.samples, [-1.0, 0.5]);
+
+        // and a trailing partial sample
+        let data = [0x00, 0x00, 0xc0, 0xff, 0xff, 0x7f, 0x00];
+        let sound = decode(&wav(WAVE_FORMAT_PCM, 1, 48000, 24, &data)).unwrap();
+        assert_eq!(sound.samples, [-0.5, 8388607.0 / 8388608.0]);
+
+        let data: Vec<u8> = [0.25f32, -2.0]
+            .iter()
+            .flat_map(|s| s.to_le_bytes())
+            .collect();
+        let sound = decode(&wav(WAVE_FORMAT_IEEE_FLOAT, 1, 22050, 32, &data)).unwrap();
+        assert_eq!(sound.samples, [0.25, -2.0]);
+    }
+
+    #[test]
+    fn wav_extensible() {
+        let mut data = wav(WAVE_FORMAT_EXTENSIBLE, 1, 8000, 8, &[255]);
+        // fmt chunk grows from 16 to 40 bytes: size, valid bits, channel
+        // mask and a GUID starting with the format
+        let mut extension = vec![22, 0, 8, 0, 4, 0, 0, 0];
+        extension.extend_from_slice(&WAVE_FORMAT_PCM.to_le_bytes());
+        extension.extend_from_slice(&[0; 14]);
+        data.splice(36..36, extension);
+        data[16] = 40;
+        let riff_size = (data.len() - 8) as u32;
+        data[4..8].copy_from_slice(&riff_size.to_le_bytes());
+
+        assert_eq!(decode(&data).unwrap().samples, [127.0 / 128.0]);
+    }
+
+    #[test]
+    fn wav_errors() {
+        assert_eq!(
+            decode(b"ID3\x04 not a sound").unwrap_err(),
+            SoundError::UnknownFormat
+        );
+        let adpcm = wav(2, 1, 8000, 4, &[0; 4]);
+        assert!(matches!(
+            decode(&adpcm).unwrap_err(),
+            SoundError::Unsupported(_)
+        ));
+        let mut truncated = wav(WAVE_FORMAT_PCM, 1, 8000, 16, &[0; 64]);
+        truncated.truncate(60);
+        assert_eq!(
+            decode(&truncated).unwrap_err(),
+            SoundError::Malformed("truncated chunk".to_string())
+        );
+        let tiny_rate = wav(WAVE_FORMAT_PCM, 1, 1, 8, &[128; 100_000]);
+        assert_eq!(
+            decode(&tiny_rate).unwrap_err(),
+            SoundError::Unsupported("sample rate of 1 Hz, outside of 8000 to 192000 Hz".into())
+        );
+        let no_channels = wav(WAVE_FORMAT_PCM, 0, 8000, 16, &[0; 4]);
+        assert!(matches!(
+            decode(&no_channels).unwrap_err(),
+            SoundError::Malformed(_)
+        ));
+    }
+
+    #[test]
+    fn ogg_vorbis() {
+        // hand-made stream of silence: 4 short blocks of 128 stereo frames
+        // after the first one
+        let data = include_bytes!("../../tests/fixtures/silence.ogg");
+        let sound = decode(data).unwrap();
+        assert_eq!((sound.sample_rate, sound.channels), (22050, 2));
+        assert_eq!(sound.samples.len(), 4 * 128 * 2);
+        assert!(sound.samples.iter().all(|&sample| sample == 0.0));
+
+        let mut broken = data.to_vec();
+        broken.truncate(100);
+        assert!(matches!(
+            decode(&broken).unwrap_err(),
+            SoundError::Malformed(_)
+        ));
+    }
+}
@@ -0,0 +1,443 @@
+//! Fixed set of voices mixed in software, on the audio thread
+
+use std::collections::BTreeMap;
+use std::sync::mpsc::{Receiver, Sender, TryRecvError};
+
+use super::{PlaySoundParams, Sound, SoundData, Voice, OUTPUT_CHANNELS, SAMPLE_RATE};
+
+/// Number of sounds that can play at the same time, [play_sound()] fails
+/// when all voices are busy
+///
+/// [play_sound()]: super::play_sound
+pub const VOICES: usize = 16;
+
+/// A change to the state of the [Mixer], sent by the main thread
+#[derive(Debug)]
+pub(crate) enum MixerCommand {
+    AddSound {
+        sound: Sound,
+        data: SoundData,
+    },
+    /// Also stops the voices playing the sound
+    RemoveSound {
+        sound: Sound,
+    },
+    Play {
+        voice: Voice,
+        sound: Sound,
+        params: PlaySoundParams,
+    },
+    /// Stops all voices playing the sound
+    StopSound {
+        sound: Sound,
+    },
+    /// Changes the volume of all voices playing the sound
+    SetSoundVolume {
+        sound: Sound,
+        volume: f32,
+    },
+    StopVoice {
+        voice: Voice,
+    },
+    /// Fades to silence over `duration` seconds, then stops
+    FadeOutVoice {
+        voice: Voice,
+        duration: f32,
+    },
+    SetVoiceVolume {
+        voice: Voice,
+        volume: f32,
+    },
+    SetVoicePan {
+        voice: Voice,
+        pan: f32,
+    },
+    SetVoicePitch {
+        voice: Voice,
+        pitch: f32,
+    },
+}
+
+/// A voice playing a sound
+#[derive(Debug)]
+struct ActiveVoice {
+    voice: Voice,
+    sound: Sound,
+    /// In frames of the sound, fractional when pitched
+    position: f64,
+    looped: bool,
+    volume: f32,
+    pan: f32,
+    pitch: f32,
+    /// Fade multiplier in `0.0..=1.0`
+    fade: f32,
+    /// Added to `fade` every output frame
+    fade_step: f32,
+}
+
+impl ActiveVoice {
+    fn fading_out(&self) -> bool {
+        self.fade_step < 0.0
+    }
+
+    /// Gain of each output channel. Panning to one side silences the other
+    /// one without making this one louder.
+    fn gains(&self) -> [f32; OUTPUT_CHANNELS] {
+        let gain = self.volume * self.fade;
+        [
+            gain * (1.0 - self.pan).min(1.0),
+            gain * (1.0 + self.pan).min(1.0),
+        ]
+    }
+
+    /// Sample of `channel` at the current position, linearly interpolated
+    /// between frames
+    fn sample(&self, data: &SoundData, channel: usize) -> f32 {
+        let frame = self.position as usize;
+        let next = if frame + 1 < data.frames() {
+            frame + 1
+        } else if self.looped {
+            0
+        } else {
+            frame
+        };
+        let t = (self.position - frame as f64) as f32;
+        let a = data.output_sample(frame, channel);
+        let b = data.output_sample(next, channel);
+        a + (b - a) * t
+    }
+}
+
+/// Change per output frame to fade over `duration` seconds
+fn fade_step(duration: f32) -> f32 {
+    1.0 / (duration * SAMPLE_RATE as f32).max(1.0)
+}
+
+/// Plays the loaded sounds on [VOICES] voices.
+///
+/// Owned by the audio thread, see [take_audio_mixer()]. It never touches the
+/// `Context`: the main thread sends it commands over a channel, which
+/// [Mixer::mix()] drains without blocking, and it reports the voices that
+/// ended over another one. The samples of removed sounds are sent back too,
+/// so they are freed on the main thread rather than in the audio callback.
+///
+/// The output only depends on the commands applied and the sizes of the
+/// buffers mixed, so it can be compared to recorded fixtures.
+///
+/// [take_audio_mixer()]: super::take_audio_mixer
+#[derive(Debug)]
+pub struct Mixer {
+    commands: Receiver<MixerCommand>,
+    ended: Sender<Voice>,
+    removed: Sender<SoundData>,
+    sounds: BTreeMap<Sound, SoundData>,
+    voices: [Option<ActiveVoice>; VOICES],
+}
+
+impl Mixer {
+    pub(crate) fn new(
+        commands: Receiver<MixerCommand>,
+        ended: Sender<Voice>,
+        removed: Sender<SoundData>,
+    ) -> Mixer {
+        Mixer {
+            commands,
+            ended,
+            removed,
+            sounds: BTreeMap::new(),
+            voices: Default::default(),
+        }
+    }
+
+    pub(crate) fn apply(&mut self, command: MixerCommand) {
+        match command {
+            MixerCommand::AddSound { sound, data } => {
+                self.sounds.insert(sound, data);
+            }
+            MixerCommand::RemoveSound { sound } => {
+                if let Some(data) = self.sounds.remove(&sound) {
+                    // only fails once the `Context` is gone, then the
+                    // samples are freed here, when nothing plays anymore
+                    let _ = self.removed.send(data);
+                }
+                self.stop_where(|active| active.sound == sound);
+            }
+            MixerCommand::Play {
+                voice,
+                sound,
+                params,
+            } => self.play(voice, sound, params),
+            MixerCommand::StopSound { sound } => self.stop_where(|active| active.sound == sound),
+            MixerCommand::SetSoundVolume { sound, volume } => self
+                .active_voices()
+                .filter(|active| active.sound == sound)
+                .for_each(|active| active.volume = volume),
+            MixerCommand::StopVoice { voice } => self.stop_where(|active| active.voice == voice),
+            MixerCommand::FadeOutVoice { voice, duration } => {
+                if let Some(active) = self.voice(voice) {
+                    active.fade_step = -fade_step(duration);
+                }
+            }
+            MixerCommand::SetVoiceVolume { voice, volume } => {
+                if let Some(active) = self.voice(voice) {
+                    active.volume = volume;
+                }
+            }
+            MixerCommand::SetVoicePan { voice, pan } => {
+                if let Some(active) = self.voice(voice) {
+                    active.pan = pan.clamp(-1.0, 1.0);
+                }
+            }
+            MixerCommand::SetVoicePitch { voice, pitch } => {
+                if let Some(active) = self.voice(voice) {
+                    active.pitch = pitch.max(0.0);
+                }
+            }
+        }
+    }
+
+    /// Apply the commands sent since the last call. Once the `Context` is
+    /// gone, nothing plays anymore.
+    fn apply_commands(&mut self) {
+        loop {
+            match self.commands.try_recv() {
+                Ok(command) => self.apply(command),
+                Err(TryRecvError::Empty) => break,
+                Err(TryRecvError::Disconnected) => {
+                    // the sounds are freed with the mixer, outside of the
+                    // audio callback
+                    self.voices = Default::default();
+                    break;
+                }
+            }
+        }
+    }
+
+    /// Start `sound` on the first free voice, nothing happens if the sound
+    /// is not loaded or all voices are busy
+    fn play(&mut self, voice: Voice, sound: Sound, params: PlaySoundParams) {
+        if !self.sounds.contains_key(&sound) {
+            return;
+        }
+        let free = match self.voices.iter_mut().find(|slot| slot.is_none()) {
+            Some(free) => free,
+            None => return,
+        };
+        let fade_in = params.fade_in > 0.0;
+        *free = Some(ActiveVoice {
+            voice,
+            sound,
+            position: 0.0,
+            looped: params.looped,
+            volume: params.volume,
+            pan: params.pan.clamp(-1.0, 1.0),
+            pitch: params.pitch.max(0.0),
+            fade: if fade_in { 0.0 } else { 1.0 },
+            fade_step: if fade_in {
+                fade_step(params.fade_in)
+            } else {
+                0.0
+            },
+        });
+    }
+
+    fn active_voices(&mut self) -> impl Iterator<Item = &mut ActiveVoice> {
+        self.voices.iter_mut().flatten()
+    }
+
+    fn voice(&mut self, voice: Voice) -> Option<&mut ActiveVoice> {
+        self.active_voices().find(|active| active.voice == voice)
+    }
+
+    /// Stopped voices are not reported as ended, the main thread already
+    /// knows about them
+    fn stop_where(&mut self, stop: impl Fn(&ActiveVoice) -> bool) {
+        for slot in &mut self.voices {
+            if slot.as_ref().is_some_and(&stop) {
+                *slot = None;
+            }
+        }
+    }
+
+    /// Overwrite `buffer` with the next `buffer.len() / OUTPUT_CHANNELS`
+    /// frames of all voices, interleaved (see [OUTPUT_CHANNELS]) and clipped
+    /// to `-1.0..=1.0`.
+    ///
+    /// Meant to be called by the platform audio callback, on any thread. It
+    /// neither locks nor waits for the main thread.
+    ///
+    /// # Panics
+    /// If `buffer.len()` is not a multiple of [OUTPUT_CHANNELS]
+    pub fn mix(&mut self, buffer: &mut [f32]) {
+        assert!(
+            buffer.len().is_multiple_of(OUTPUT_CHANNELS),
+            "audio buffer length {} is not a multiple of {} channels",
+            buffer.len(),
+            OUTPUT_CHANNELS
+        );
+        self.apply_commands();
+        buffer.fill(0.0);
+        for slot in &mut self.voices {
+            let playing = match slot {
+                Some(active) => match self.sounds.get(&active.sound) {
+                    Some(data) if data.frames() > 0 => active.mix(data, buffer),
+                    _ => false,
+                },
+                None => continue,
+            };
+            if !playing {
+                if let Some(active) = slot.take() {
+                    // the main thread may be gone, then nobody cares
+                    let _ = self.ended.send(active.voice);
+                }
+            }
+        }
+        buffer
+            .iter_mut()
+            .for_each(|sample| *sample = sample.clamp(-1.0, 1.0));
+    }
+}
+
+impl ActiveVoice {
+    /// Add the voice to `buffer`, returns whether it is still playing
+    fn mix(&mut self, data: &SoundData, buffer: &mut [f32]) -> bool {
+        for frame in buffer.chunks_exact_mut(OUTPUT_CHANNELS) {
+            let gains = self.gains();
+            for (channel, (out, gain)) in frame.iter_mut().zip(gains).enumerate() {
+                *out += self.sample(data, channel) * gain;
+            }
+
+            if self.fade_step != 0.0 {
+                self.fade = (self.fade + self.fade_step).clamp(0.0, 1.0);
+                if self.fade == 0.0 && self.fading_out() {
+                    return false;
+                }
+                if self.fade == 1.0 {
+                    self.fade_step = 0.0;
+                }
+            }
+            self.position += self.pitch as f64;
+            if self.position >= data.frames() as f64 {
+                if !self.looped {
+                    return false;
+                }
+                self.position %= data.frames() as f64;
+            }
+        }
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::check_golden;
+    use std::fmt::Write;
+    use std::sync::mpsc::channel;
+
+    /// Seconds lasting `frames` output frames
+    fn seconds(frames: f32) -> f32 {
+        frames / SAMPLE_RATE as f32
+    }
+
+    #[test]
+    fn golden_mix() {
+        let (commands, receiver) = channel();
+        let (sender, ended) = channel();
+        let (removed_sender, removed) = channel();
+        let mut mixer = Mixer::new(receiver, sender, removed_sender);
+        let (ramp, stereo) = (Sound(0), Sound(1));
+        commands
+            .send(MixerCommand::AddSound {
+                sound: ramp,
+                data: SoundData {
+                    sample_rate: SAMPLE_RATE,
+                    channels: 1,
+                    samples: (0..8).map(|i| i as f32 / 4.0 - 1.0).collect(),
+                },
+            })
+            .unwrap();
+        commands
+            .send(MixerCommand::AddSound {
+                sound: stereo,
+                data: SoundData {
+                    sample_rate: SAMPLE_RATE,
+                    channels: 2,
+                    samples: vec![0.5, -0.5, 0.25, -0.25, 0.0, 0.0],
+                },
+            })
+            .unwrap();
+        commands
+            .send(MixerCommand::Play {
+                voice: Voice(0),
+                sound: ramp,
+                params: PlaySoundParams {
+                    looped: true,
+                    pan: -0.5,
+                    pitch: 0.75,
+                    fade_in: seconds(4.0),
+                    ..PlaySoundParams::default()
+                },
+            })
+            .unwrap();
+        commands
+            .send(MixerCommand::Play {
+                voice: Voice(1),
+                sound: stereo,
+                params: PlaySoundParams {
+                    volume: 0.5,
+                    pitch: 1.5,
+                    ..PlaySoundParams::default()
+                },
+            })
+            .unwrap();
+
+        let mut output = vec![];
+        let mut buffer = [0.0; 6 * OUTPUT_CHANNELS];
+        for _ in 0..3 {
+            mixer.mix(&mut buffer);
+            output.extend_from_slice(&buffer);
+        }
+        assert_eq!(ended.try_recv(), Ok(Voice(1)));
+        commands
+            .send(MixerCommand::FadeOutVoice {
+                voice: Voice(0),
+                duration: seconds(8.0),
+            })
+            .unwrap();
+        for _ in 0..2 {
+            mixer.mix(&mut buffer);
+            output.extend_from_slice(&buffer);
+        }
+        assert_eq!(ended.try_recv(), Ok(Voice(0)));
+
+        // removed samples go back to be freed by the main thread
+        commands
+            .send(MixerCommand::RemoveSound { sound: stereo })
+            .unwrap();
+        mixer.mix(&mut buffer);
+        assert_eq!(removed.try_recv().map(|data| data.channels), Ok(2));
+
+        // the voices stop with the main thread
+        commands
+            .send(MixerCommand::Play {
+                voice: Voice(2),
+                sound: ramp,
+                params: PlaySoundParams::default(),
+            })
+            .unwrap();
+        drop(commands);
+        mixer.mix(&mut buffer);
+        assert_eq!(buffer, [0.0; 6 * OUTPUT_CHANNELS]);
+
+        let mut text = String::new();
+        for frame in output.chunks(OUTPUT_CHANNELS) {
+            writeln!(text, "{} {}", frame[0], frame[1]).unwrap();
+        }
+        check_golden(
+            "mix.txt",
+            include_str!("../../tests/fixtures/mix.txt"),
+            &text,
+        );
+    }
+}
@@ -0,0 +1,261 @@
+//! Conversion of decoded sounds to the mixer output rate and channels
+
+use std::f64::consts::PI;
+
+use super::{ResampleQuality, SoundData, SoundError, OUTPUT_CHANNELS, SAMPLE_RATE};
+
+/// Zero crossings of the sinc on each side of the interpolated position
+const SINC_ZERO_CROSSINGS: f64 = 16.0;
+
+/// Longest converted sound, an hour at [SAMPLE_RATE]
+const MAX_FRAMES: u64 = 3600 * SAMPLE_RATE as u64;
+
+/// Convert `data` to [OUTPUT_CHANNELS] channels at [SAMPLE_RATE]. Fails
+/// with [SoundError::Unsupported], before allocating, if the result would
+/// be longer than [MAX_FRAMES].
+pub(crate) fn convert(data: SoundData, quality: ResampleQuality) -> Result<SoundData, SoundError> {
+    let output_frames = output_frames(&data)
+        .filter(|&frames| frames <= MAX_FRAMES)
+        .ok_or_else(|| {
+            SoundError::Unsupported(format!(
+                "sound of {} frames at {} Hz is longer than {} seconds",
+                data.frames(),
+                data.sample_rate,
+                MAX_FRAMES / SAMPLE_RATE as u64
+            ))
+        })?;
+    let data = remix(data);
+    if data.sample_rate == SAMPLE_RATE {
+        return Ok(data);
+    }
+    Ok(resample(&data, output_frames as usize, quality))
+}
+
+/// Frames of `data` once at [SAMPLE_RATE], `None` on overflow
+fn output_frames(data: &SoundData) -> Option<u64> {
+    let frames = data.frames() as u64;
+    let (from, to) = (data.sample_rate as u64, SAMPLE_RATE as u64);
+    Some(frames.checked_mul(to)?.div_ceil(from))
+}
+
+/// Mono is played on both sides. With more channels, the first two are left
+/// and right and the others are added to both sides at -3 dB, as if centered.
+fn remix(data: SoundData) -> SoundData {
+    let channels = data.channels as usize;
+    if channels == OUTPUT_CHANNELS {
+        return data;
+    }
+    let samples = data
+        .samples
+        .chunks_exact(channels)
+        .flat_map(|frame| match frame {
+            [mono] => [*mono; OUTPUT_CHANNELS],
+            [left, right, centered @ ..] => {
+                let center = centered.iter().sum::<f32>() * std::f32::consts::FRAC_1_SQRT_2;
+                [left + center, right + center]
+            }
+            [] => unreachable!("sounds have at least one channel"),
+        })
+        .collect();
+    SoundData {
+        sample_rate: data.sample_rate,
+        channels: OUTPUT_CHANNELS as u16,
+        samples,
+    }
+}
+
+fn resample(data: &SoundData, output_frames: usize, quality: ResampleQuality) -> SoundData {
+    let step = data.sample_rate as f64 / SAMPLE_RATE as f64;
+
+    let mut samples = Vec::with_capacity(output_frames * OUTPUT_CHANNELS);
+    for frame in 0..output_frames {
+        let position = frame as f64 * step;
+        for channel in 0..OUTPUT_CHANNELS {
+            let channel = Channel { data, channel };
+            samples.push(match quality {
+                ResampleQuality::Linear => channel.linear(position),
+                ResampleQuality::Cubic => channel.cubic(position),
+                ResampleQuality::WindowedSinc => channel.windowed_sinc(position, step),
+            });
+        }
+    }
+    SoundData {
+        sample_rate: SAMPLE_RATE,
+        channels: OUTPUT_CHANNELS as u16,
+        samples,
+    }
+}
+
+/// One channel of a sound with [OUTPUT_CHANNELS] channels
+struct Channel<'a> {
+    data: &'a SoundData,
+    channel: usize,
+}
+
+impl Channel<'_> {
+    /// Sample of `frame`, the first and last ones are repeated outside of
+    /// the sound
+    fn clamped(&self, frame: i64) -> f64 {
+        let frame = frame.clamp(0, self.data.frames() as i64 - 1) as usize;
+        self.data.samples[frame * OUTPUT_CHANNELS + self.channel] as f64
+    }
+
+    /// Sample of `frame`, silence outside of the sound
+    fn padded(&self, frame: i64) -> f64 {
+        if (0..self.data.frames() as i64).contains(&frame) {
+            self.data.samples[frame as usize * OUTPUT_CHANNELS + self.channel] as f64
+        } else {
+            0.0
+        }
+    }
+
+    fn linear(&self, position: f64) -> f32 {
+        let frame = position.floor();
+        let t = position - frame;
+        let (a, b) = (self.clamped(frame as i64), self.clamped(frame as i64 + 1));
+        (a + (b - a) * t) as f32
+    }
+
+    /// Catmull-Rom spline through the two frames around `position` and
+    /// their neighbours
+    fn cubic(&self, position: f64) -> f32 {
+        let frame = position.floor();
+        let t = position - frame;
+        let frame = frame as i64;
+        let [p0, p1, p2, p3] = [-1, 0, 1, 2].map(|offset| self.clamped(frame + offset));
+        let sample = p1
+            + 0.5
+                * t
+                * (p2 - p0
+                    + t * (2.0 * p0 - 5.0 * p1 + 4.0 * p2 - p3 + t * (3.0 * (p1 - p2) + p3 - p0)));
+        sample as f32
+    }
+
+    /// Sinc with a Blackman window. When `step` is above `1.0` the sound is
+    /// downsampled, and the cutoff is lowered to the new Nyquist frequency
+    /// against aliasing.
+    fn windowed_sinc(&self, position: f64, step: f64) -> f32 {
+        let cutoff = (1.0 / step).min(1.0);
+        let half_width = SINC_ZERO_CROSSINGS / cutoff;
+        let first = (position - half_width).ceil() as i64;
+        let last = (position + half_width).floor() as i64;
+        let sample: f64 = (first..=last)
+            .map(|frame| {
+                let x = position - frame as f64;
+                let window = blackman(x / half_width);
+                self.padded(frame) * cutoff * sinc(cutoff * x) * window
+            })
+            .sum();
+        sample as f32
+    }
+}
+
+/// Normalized sinc, `sin(πx) / πx`
+fn sinc(x: f64) -> f64 {
+    if x == 0.0 {
+        1.0
+    } else {
+        (PI * x).sin() / (PI * x)
+    }
+}
+
+/// Blackman window over `-1.0..=1.0`
+fn blackman(x: f64) -> f64 {
+    0.42 + 0.5 * (PI * x).cos() + 0.08 * (2.0 * PI * x).cos()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sound(sample_rate: u32, channels: u16, samples: Vec<f32>) -> SoundData {
+        SoundData {
+            sample_rate,
+            channels,
+            samples,
+        }
+    }
+
+    /// Largest difference with a 1 kHz sine, away from the ends where the
+    /// sinc is cut off
+    fn sine_error(quality: ResampleQuality) -> f32 {
+        let sine =
+            |rate: u32, frame: usize| (2.0 * PI * 1000.0 * frame as f64 / rate as f64).sin() as f32;
+        let samples = (0..480).flat_map(|frame| [sine(48000, frame); 2]).collect();
+        let resampled = convert(sound(48000, 2, samples), quality).unwrap();
+        assert_eq!(resampled.frames(), 441);
+        (50..390)
+            .map(|frame| (resampled.samples[frame * 2] - sine(SAMPLE_RATE, frame)).abs())
+            .fold(0.0, f32::max)
+    }
+
+    #[test]
+    fn remix_channels() {
+        let mono = convert(
+            sound(SAMPLE_RATE, 1, vec![0.5, -0.25]),
+            ResampleQuality::Linear,
+        )
+        .unwrap();
+        assert_eq!(mono.channels, 2);
+        assert_eq!(mono.samples, [0.5, 0.5, -0.25, -0.25]);
+
+        let stereo = vec![0.5, -0.25, 1.0, 0.0];
+        let converted = convert(
+            sound(SAMPLE_RATE, 2, stereo.clone()),
+            ResampleQuality::Linear,
+        )
+        .unwrap();
+        assert_eq!(converted.samples, stereo);
+
+        let surround = convert(
+            sound(SAMPLE_RATE, 3, vec![0.25, -0.25, 0.5]),
+            ResampleQuality::Linear,
+        )
+        .unwrap();
+        let center = 0.5 * std::f32::consts::FRAC_1_SQRT_2;
+        assert_eq!(surround.samples, [0.25 + center, -0.25 + center]);
+    }
+
+    #[test]
+    fn upsample() {
+        let data = sound(22050, 1, vec![0.5, -0.5, 0.5]);
+        let linear = convert(data, ResampleQuality::Linear).unwrap();
+        assert_eq!((linear.sample_rate, linear.frames()), (SAMPLE_RATE, 6));
+        assert_eq!(
+            linear.samples,
+            [0.5, 0.5, 0.0, 0.0, -0.5, -0.5, 0.0, 0.0, 0.5, 0.5, 0.5, 0.5]
+        );
+
+        // the spline goes through the original samples and overshoots
+        // between them
+        let data = sound(22050, 1, vec![0.0, 0.0, 1.0, 1.0]);
+        let cubic = convert(data, ResampleQuality::Cubic).unwrap();
+        let left: Vec<_> = cubic.samples.iter().step_by(2).copied().collect();
+        assert_eq!(left, [0.0, -0.0625, 0.0, 0.5, 1.0, 1.0625, 1.0, 1.0]);
+    }
+
+    /// Checked before allocating anything
+    #[test]
+    fn too_long_is_rejected() {
+        let slow = sound(1, 1, vec![0.0; 100_000]);
+        assert!(matches!(
+            convert(slow, ResampleQuality::Linear),
+            Err(SoundError::Unsupported(_))
+        ));
+    }
+
+    #[test]
+    fn downsample_quality() {
+        let linear = sine_error(ResampleQuality::Linear);
+        let cubic = sine_error(ResampleQuality::Cubic);
+        let sinc = sine_error(ResampleQuality::WindowedSinc);
+        assert!(
+            sinc < cubic && cubic < linear,
+            "{} {} {}",
+            sinc,
+            cubic,
+            linear
+        );
+        assert!(sinc < 1e-3, "{}", sinc);
+    }
+}
@@ -0,0 +1,293 @@
+//! Gamepad state, fed by the `gamepad_*_event()` handlers
+
+use std::collections::{BTreeMap, BTreeSet};
+
+use crate::replay::InputCall;
+use crate::{with_context, with_context_ref};
+
+/// Identifies a gamepad from connection to disconnection, chosen by the
+/// platform
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct GamepadId(pub usize);
+
+/// Buttons named by their position, `South` being A on Xbox and Cross on
+/// PlayStation controllers
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum GamepadButton {
+    South,
+    East,
+    West,
+    North,
+    LeftShoulder,
+    RightShoulder,
+    Select,
+    Start,
+    Guide,
+    LeftStick,
+    RightStick,
+    DPadUp,
+    DPadDown,
+    DPadLeft,
+    DPadRight,
+}
+
+/// Sticks range from `-1.0` to `1.0`, positive being right and down.
+/// Triggers range from `0.0` to `1.0`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum GamepadAxis {
+    LeftStickX,
+    LeftStickY,
+    RightStickX,
+    RightStickY,
+    LeftTrigger,
+    RightTrigger,
+}
+
+/// Axis values below this are reported as `0.0` unless changed with
+/// [set_gamepad_dead_zone()]
+pub const DEFAULT_GAMEPAD_DEAD_ZONE: f32 = 0.1;
+
+#[derive(Debug, Default)]
+struct GamepadState {
+    buttons_down: BTreeSet<GamepadButton>,
+    /// Pressed since the end of the last frame
+    buttons_pressed: BTreeSet<GamepadButton>,
+    /// Released since the end of the last frame
+    buttons_released: BTreeSet<GamepadButton>,
+    /// Values as reported, without dead zone
+    axes: BTreeMap<GamepadAxis, f32>,
+}
+
+/// State of all connected gamepads, stored in the `Context`
+#[derive(Debug)]
+pub(crate) struct Gamepads {
+    pads: BTreeMap<GamepadId, GamepadState>,
+    dead_zone: f32,
+}
+
+impl Default for Gamepads {
+    fn default() -> Self {
+        Self {
+            pads: BTreeMap::new(),
+            dead_zone: DEFAULT_GAMEPAD_DEAD_ZONE,
+        }
+    }
+}
+
+impl Gamepads {
+    /// Reset the state that only lasts for one frame, returns the number of
+    /// cleared pressed and released buttons
+    pub(crate) fn end_frame(&mut self) -> usize {
+        self.pads
+            .values_mut()
+            .map(|pad| {
+                let cleared = pad.buttons_pressed.len() + pad.buttons_released.len();
+                pad.buttons_pressed.clear();
+                pad.buttons_released.clear();
+                cleared
+            })
+            .sum()
+    }
+
+    fn apply_dead_zone(&self, value: f32) -> f32 {
+        if value.abs() < self.dead_zone {
+            0.0
+        } else {
+            // rescale so values still start at 0 right outside the dead zone
+            value.signum() * (value.abs() - self.dead_zone) / (1.0 - self.dead_zone)
+        }
+    }
+}
+
+/// A gamepad was plugged in. Events for gamepads that are not connected are
+/// ignored.
+pub fn gamepad_connected_event(id: GamepadId) {
+    with_context(|ctx| {
+        ctx.record(InputCall::GamepadConnected { id });
+        ctx.gamepads.pads.entry(id).or_default();
+    });
+}
+
+/// A gamepad was unplugged, its state is dropped
+pub fn gamepad_disconnected_event(id: GamepadId) {
+    with_context(|ctx| {
+        ctx.record(InputCall::GamepadDisconnected { id });
+        ctx.gamepads.pads.remove(&id);
+    });
+}
+
+pub fn gamepad_button_down_event(id: GamepadId, button: GamepadButton) {
+    with_context(|ctx| {
+        ctx.record(InputCall::GamepadButtonDown { id, button });
+        if let Some(pad) = ctx.gamepads.pads.get_mut(&id) {
+            pad.buttons_down.insert(button);
+            pad.buttons_pressed.insert(button);
+        }
+    });
+}
+
+pub fn gamepad_button_up_event(id: GamepadId, button: GamepadButton) {
+    with_context(|ctx| {
+        ctx.record(InputCall::GamepadButtonUp { id, button });
+        if let Some(pad) = ctx.gamepads.pads.get_mut(&id) {
+            pad.buttons_down.remove(&button);
+            pad.buttons_released.insert(button);
+        }
+    });
+}
+
+pub fn gamepad_axis_event(id: GamepadId, axis: GamepadAxis, value: f32) {
+    with_context(|ctx| {
+        ctx.record(InputCall::GamepadAxis { id, axis, value });
+        if let Some(pad) = ctx.gamepads.pads.get_mut(&id) {
+            pad.axes.insert(axis, value.clamp(-1.0, 1.0));
+        }
+    });
+}
+
+/// Connected gamepads, ordered by id.
+///
+/// # Panics
+/// On any [ContextError](crate::ContextError)
+#[track_caller]
+pub fn connected_gamepads() -> Vec<GamepadId> {
+    with_context_ref(|context| context.gamepads.pads.keys().copied().collect())
+}
+
+/// # Panics
+/// On any [ContextError](crate::ContextError)
+#[track_caller]
+pub fn is_gamepad_connected(id: GamepadId) -> bool {
+    with_context_ref(|context| context.gamepads.pads.contains_key(&id))
+}
+
+/// Whether `button` is held down. `false` for gamepads that are not
+/// connected.
+///
+/// # Panics
+/// On any [ContextError](crate::ContextError)
+#[track_caller]
+pub fn is_gamepad_button_down(id: GamepadId, button: GamepadButton) -> bool {
+    with_context_ref(|context| {
+        context
+            .gamepads
+            .pads
+            .get(&id)
+            .is_some_and(|pad| pad.buttons_down.contains(&button))
+    })
+}
+
+/// Whether `button` was pressed during the current frame.
+///
+/// # Panics
+/// On any [ContextError](crate::ContextError)
+#[track_caller]
+pub fn is_gamepad_button_pressed(id: GamepadId, button: GamepadButton) -> bool {
+    with_context_ref(|context| {
+        context
+            .gamepads
+            .pads
+            .get(&id)
+            .is_some_and(|pad| pad.buttons_pressed.contains(&button))
+    })
+}
+
+/// Whether `button` was released during the current frame.
+///
+/// # Panics
+/// On any [ContextError](crate::ContextError)
+#[track_caller]
+pub fn is_gamepad_button_released(id: GamepadId, button: GamepadButton) -> bool {
+    with_context_ref(|context| {
+        context
+            .gamepads
+            .pads
+            .get(&id)
+            .is_some_and(|pad| pad.buttons_released.contains(&button))
+    })
+}
+
+/// Value of `axis` after applying the dead zone, see
+/// [set_gamepad_dead_zone()]. `0.0` for gamepads that are not connected.
+///
+/// # Panics
+/// On any [ContextError](crate::ContextError)
+#[track_caller]
+pub fn gamepad_axis(id: GamepadId, axis: GamepadAxis) -> f32 {
+    with_context_ref(|context| {
+        let value = context
+            .gamepads
+            .pads
+            .get(&id)
+            .and_then(|pad| pad.axes.get(&axis))
+            .copied()
+            .unwrap_or(0.0);
+        context.gamepads.apply_dead_zone(value)
+    })
+}
+
+/// Whether `dead_zone` can be passed to [set_gamepad_dead_zone()]
+pub(crate) fn is_valid_dead_zone(dead_zone: f32) -> bool {
+    (0.0..1.0).contains(&dead_zone)
+}
+
+/// Axis values closer to `0.0` than `dead_zone` are reported as `0.0` by
+/// [gamepad_axis()], values outside are rescaled to still cover the whole
+/// range. Applies to all gamepads.
+///
+/// # Panics
+/// - if `dead_zone` is not in `0.0..1.0`
+/// - on any [ContextError](crate::ContextError)
+#[track_caller]
+pub fn set_gamepad_dead_zone(dead_zone: f32) {
+    assert!(
+        is_valid_dead_zone(dead_zone),
+        "gamepad dead zone must be in 0.0..1.0, got {}",
+        dead_zone
+    );
+    with_context(|context| {
+        context.record(InputCall::SetGamepadDeadZone { dead_zone });
+        context.gamepads.dead_zone = dead_zone;
+    });
+}
+
+/// # Panics
+/// On any [ContextError](crate::ContextError)
+#[track_caller]
+pub fn gamepad_dead_zone() -> f32 {
+    with_context_ref(|context| context.gamepads.dead_zone)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::end_frame;
+    use crate::test_utils::fresh_context;
+
+    #[test]
+    fn injected_events() {
+        let _context = fresh_context();
+        let pad = GamepadId(3);
+        gamepad_button_down_event(pad, GamepadButton::South);
+        assert!(!is_gamepad_button_down(pad, GamepadButton::South));
+
+        gamepad_connected_event(pad);
+        gamepad_button_down_event(pad, GamepadButton::South);
+        gamepad_axis_event(pad, GamepadAxis::LeftStickX, 0.05);
+        gamepad_axis_event(pad, GamepadAxis::LeftStickY, -0.75);
+        assert_eq!(connected_gamepads(), [pad]);
+        assert!(is_gamepad_button_pressed(pad, GamepadButton::South));
+        assert_eq!(gamepad_axis(pad, GamepadAxis::LeftStickX), 0.0);
+        set_gamepad_dead_zone(0.5);
+        assert_eq!(gamepad_axis(pad, GamepadAxis::LeftStickY), -0.5);
+
+        assert_eq!(end_frame().gamepad_buttons_cleared, 1);
+        gamepad_button_up_event(pad, GamepadButton::South);
+        assert!(!is_gamepad_button_down(pad, GamepadButton::South));
+        assert!(is_gamepad_button_released(pad, GamepadButton::South));
+
+        gamepad_disconnected_event(pad);
+        assert!(!is_gamepad_connected(pad));
+        assert_eq!(gamepad_axis(pad, GamepadAxis::LeftStickY), 0.0);
+    }
+}
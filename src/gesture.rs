@@ -0,0 +1,569 @@
+//! Gestures recognized from the touch events of the `Context`
+
+use std::collections::BTreeMap;
+use std::f32::consts::PI;
+
+use crate::input::{
+    register_input_subscriber, InputSubscriber, MiniquadInputEvent, MouseButton, TouchPhase,
+};
+use crate::{mouse_button_down_event, mouse_button_up_event};
+
+/// Thresholds used by [GestureRecognizer]. Times are in seconds, distances
+/// in pixels.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct GestureConfig {
+    /// Longest touch still counted as a tap
+    pub tap_max_duration: f64,
+    /// Farthest a finger may move and still tap or long-press
+    pub tap_max_distance: f32,
+    /// Longest time between two taps of a double tap
+    pub double_tap_max_interval: f64,
+    /// How long a finger has to stay down for a long press
+    pub long_press_duration: f64,
+    /// Shortest distance counted as a swipe
+    pub swipe_min_distance: f32,
+    /// Longest touch still counted as a swipe
+    pub swipe_max_duration: f64,
+    /// Smallest change of the distance between two fingers, as a ratio of
+    /// the initial distance, counted as a pinch
+    pub pinch_min_scale_change: f32,
+    /// Smallest rotation of two fingers, in radians, counted as a rotation
+    pub rotate_min_angle: f32,
+    /// Turn taps into left clicks and long presses into right clicks by
+    /// calling the mouse event handlers, like the `Context` does with
+    /// touches. Usually combined with
+    /// [TouchMouseSimulation::Off](crate::input::TouchMouseSimulation::Off).
+    pub simulate_mouse: bool,
+}
+
+impl Default for GestureConfig {
+    fn default() -> Self {
+        Self {
+            tap_max_duration: 0.3,
+            tap_max_distance: 10.0,
+            double_tap_max_interval: 0.3,
+            long_press_duration: 0.5,
+            swipe_min_distance: 50.0,
+            swipe_max_duration: 0.5,
+            pinch_min_scale_change: 0.05,
+            rotate_min_angle: 0.1,
+            simulate_mouse: false,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SwipeDirection {
+    Left,
+    Right,
+    Up,
+    Down,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Gesture {
+    Tap {
+        x: f32,
+        y: f32,
+    },
+    /// Second tap of a double tap, the first one is reported as a
+    /// [Gesture::Tap]
+    DoubleTap {
+        x: f32,
+        y: f32,
+    },
+    LongPress {
+        x: f32,
+        y: f32,
+    },
+    Swipe {
+        start_x: f32,
+        start_y: f32,
+        end_x: f32,
+        end_y: f32,
+        direction: SwipeDirection,
+    },
+    /// Reported on every move of two fingers once the threshold is passed
+    Pinch {
+        center_x: f32,
+        center_y: f32,
+        /// Distance between the fingers relative to when the second one
+        /// touched down
+        scale: f32,
+    },
+    /// Reported on every move of two fingers once the threshold is passed
+    Rotate {
+        center_x: f32,
+        center_y: f32,
+        /// Radians since the second finger touched down, clockwise on screen
+        angle: f32,
+    },
+}
+
+#[derive(Debug)]
+struct TrackedTouch {
+    start_x: f32,
+    start_y: f32,
+    start_time: f64,
+    x: f32,
+    y: f32,
+    /// Moved farther than [GestureConfig::tap_max_distance]
+    moved: bool,
+    /// Part of a multi-finger gesture, no single finger gestures
+    multi: bool,
+    long_pressed: bool,
+}
+
+impl TrackedTouch {
+    fn distance(&self) -> f32 {
+        (self.x - self.start_x).hypot(self.y - self.start_y)
+    }
+}
+
+/// The first two fingers of a multi-finger gesture
+#[derive(Debug)]
+struct TwoFingers {
+    ids: (u64, u64),
+    start_distance: f32,
+    start_angle: f32,
+    pinching: bool,
+    rotating: bool,
+}
+
+/// Turns the touch events of the `Context` into [Gesture]s.
+///
+/// The recognizer has its own [InputSubscriber], so it sees every touch no
+/// matter who else consumes input events.
+#[derive(Debug)]
+pub struct GestureRecognizer {
+    config: GestureConfig,
+    subscriber: InputSubscriber,
+    touches: BTreeMap<u64, TrackedTouch>,
+    two_fingers: Option<TwoFingers>,
+    /// Time and position of the last tap, for double taps
+    last_tap: Option<(f64, f32, f32)>,
+}
+
+impl GestureRecognizer {
+    /// # Panics
+    /// On any [ContextError](crate::ContextError)
+    #[track_caller]
+    pub fn new(config: GestureConfig) -> Self {
+        Self {
+            config,
+            subscriber: register_input_subscriber(),
+            touches: BTreeMap::new(),
+            two_fingers: None,
+            last_tap: None,
+        }
+    }
+
+    pub fn config(&self) -> &GestureConfig {
+        &self.config
+    }
+
+    pub fn set_config(&mut self, config: GestureConfig) {
+        self.config = config;
+    }
+
+    /// Process the touch events received since the last call. `time` is the
+    /// current time in seconds, on any clock as long as it is monotonic.
+    ///
+    /// Must not be called from inside a `with_context()` closure.
+    ///
+    /// # Panics
+    /// On any [ContextError](crate::ContextError)
+    #[track_caller]
+    pub fn update(&mut self, time: f64) -> Vec<Gesture> {
+        let mut gestures = vec![];
+        for event in self.subscriber.drain() {
+            if let MiniquadInputEvent::Touch { phase, id, x, y } = event {
+                self.touch(phase, id, x, y, time, &mut gestures);
+            }
+        }
+        self.check_long_presses(time, &mut gestures);
+
+        if self.config.simulate_mouse {
+            for gesture in &gestures {
+                match *gesture {
+                    Gesture::Tap { x, y } | Gesture::DoubleTap { x, y } => {
+                        mouse_button_down_event(MouseButton::Left, x, y);
+                        mouse_button_up_event(MouseButton::Left, x, y);
+                    }
+                    Gesture::LongPress { x, y } => {
+                        mouse_button_down_event(MouseButton::Right, x, y);
+                        mouse_button_up_event(MouseButton::Right, x, y);
+                    }
+                    _ => {}
+                }
+            }
+            // Drop the events we just caused
+            self.subscriber.drain();
+        }
+        gestures
+    }
+
+    fn touch(
+        &mut self,
+        phase: TouchPhase,
+        id: u64,
+        x: f32,
+        y: f32,
+        time: f64,
+        gestures: &mut Vec<Gesture>,
+    ) {
+        match phase {
+            TouchPhase::Started => {
+                self.touches.insert(
+                    id,
+                    TrackedTouch {
+                        start_x: x,
+                        start_y: y,
+                        start_time: time,
+                        x,
+                        y,
+                        moved: false,
+                        multi: false,
+                        long_pressed: false,
+                    },
+                );
+                if self.touches.len() > 1 {
+                    self.touches
+                        .values_mut()
+                        .for_each(|touch| touch.multi = true);
+                }
+                if self.two_fingers.is_none() && self.touches.len() == 2 {
+                    self.start_two_fingers();
+                }
+            }
+            TouchPhase::Moved => {
+                if let Some(touch) = self.touches.get_mut(&id) {
+                    touch.x = x;
+                    touch.y = y;
+                    if touch.distance() > self.config.tap_max_distance {
+                        touch.moved = true;
+                    }
+                }
+                self.update_two_fingers(id, gestures);
+            }
+            TouchPhase::Ended => {
+                if let Some(touch) = self.touches.remove(&id) {
+                    self.end_two_fingers(id);
+                    self.released(touch, x, y, time, gestures);
+                }
+            }
+            TouchPhase::Cancelled => {
+                self.touches.remove(&id);
+                self.end_two_fingers(id);
+            }
+        }
+    }
+
+    /// Single finger gestures of a finger that just lifted
+    fn released(
+        &mut self,
+        mut touch: TrackedTouch,
+        x: f32,
+        y: f32,
+        time: f64,
+        gestures: &mut Vec<Gesture>,
+    ) {
+        if touch.multi || touch.long_pressed {
+            return;
+        }
+        touch.x = x;
+        touch.y = y;
+        let duration = time - touch.start_time;
+        let distance = touch.distance();
+
+        if !touch.moved
+            && distance <= self.config.tap_max_distance
+            && duration <= self.config.tap_max_duration
+        {
+            let double = self.last_tap.is_some_and(|(last_time, last_x, last_y)| {
+                time - last_time <= self.config.double_tap_max_interval
+                    && (x - last_x).hypot(y - last_y) <= self.config.tap_max_distance
+            });
+            if double {
+                self.last_tap = None;
+                gestures.push(Gesture::DoubleTap { x, y });
+            } else {
+                self.last_tap = Some((time, x, y));
+                gestures.push(Gesture::Tap { x, y });
+            }
+        } else if distance >= self.config.swipe_min_distance
+            && duration <= self.config.swipe_max_duration
+        {
+            let (dx, dy) = (x - touch.start_x, y - touch.start_y);
+            let direction = if dx.abs() >= dy.abs() {
+                if dx < 0.0 {
+                    SwipeDirection::Left
+                } else {
+                    SwipeDirection::Right
+                }
+            } else if dy < 0.0 {
+                SwipeDirection::Up
+            } else {
+                SwipeDirection::Down
+            };
+            gestures.push(Gesture::Swipe {
+                start_x: touch.start_x,
+                start_y: touch.start_y,
+                end_x: x,
+                end_y: y,
+                direction,
+            });
+        }
+    }
+
+    fn check_long_presses(&mut self, time: f64, gestures: &mut Vec<Gesture>) {
+        for touch in self.touches.values_mut() {
+            if !touch.multi
+                && !touch.moved
+                && !touch.long_pressed
+                && time - touch.start_time >= self.config.long_press_duration
+            {
+                touch.long_pressed = true;
+                gestures.push(Gesture::LongPress {
+                    x: touch.x,
+                    y: touch.y,
+                });
+            }
+        }
+    }
+
+    /// Center, distance and angle between the two fingers
+    fn two_finger_geometry(&self, ids: (u64, u64)) -> Option<(f32, f32, f32, f32)> {
+        let a = self.touches.get(&ids.0)?;
+        let b = self.touches.get(&ids.1)?;
+        let (dx, dy) = (b.x - a.x, b.y - a.y);
+        Some((
+            (a.x + b.x) / 2.0,
+            (a.y + b.y) / 2.0,
+            dx.hypot(dy),
+            dy.atan2(dx),
+        ))
+    }
+
+    fn start_two_fingers(&mut self) {
+        let mut ids = self.touches.keys().copied();
+        let ids = match (ids.next(), ids.next()) {
+            (Some(a), Some(b)) => (a, b),
+            _ => return,
+        };
+        if let Some((_, _, start_distance, start_angle)) = self.two_finger_geometry(ids) {
+            self.two_fingers = Some(TwoFingers {
+                ids,
+                start_distance,
+                start_angle,
+                pinching: false,
+                rotating: false,
+            });
+        }
+    }
+
+    fn update_two_fingers(&mut self, id: u64, gestures: &mut Vec<Gesture>) {
+        let ids = match &self.two_fingers {
+            Some(two_fingers) if two_fingers.ids.0 == id || two_fingers.ids.1 == id => {
+                two_fingers.ids
+            }
+            _ => return,
+        };
+        let (center_x, center_y, distance, angle) = match self.two_finger_geometry(ids) {
+            Some(geometry) => geometry,
+            None => return,
+        };
+        let config = self.config;
+        let two_fingers = self.two_fingers.as_mut().unwrap();
+
+        if two_fingers.start_distance > 0.0 {
+            let scale = distance / two_fingers.start_distance;
+            if (scale - 1.0).abs() >= config.pinch_min_scale_change {
+                two_fingers.pinching = true;
+            }
+            if two_fingers.pinching {
+                gestures.push(Gesture::Pinch {
+                    center_x,
+                    center_y,
+                    scale,
+                });
+            }
+        }
+
+        let mut angle = angle - two_fingers.start_angle;
+        if angle > PI {
+            angle -= 2.0 * PI;
+        } else if angle < -PI {
+            angle += 2.0 * PI;
+        }
+        if angle.abs() >= config.rotate_min_angle {
+            two_fingers.rotating = true;
+        }
+        if two_fingers.rotating {
+            gestures.push(Gesture::Rotate {
+                center_x,
+                center_y,
+                angle,
+            });
+        }
+    }
+
+    fn end_two_fingers(&mut self, id: u64) {
+        if let Some(two_fingers) = &self.two_fingers {
+            if two_fingers.ids.0 == id || two_fingers.ids.1 == id {
+                self.two_fingers = None;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::input::{
+        is_mouse_button_down, is_mouse_button_pressed, set_touch_mouse_simulation,
+        TouchMouseSimulation,
+    };
+    use crate::test_utils::fresh_context;
+    use crate::{end_frame, touch_event};
+
+    /// Center and angle of the only gesture, which must be a rotation.
+    /// Angles are compared with a tolerance: Miri makes `atan2()` inexact.
+    fn rotation(gestures: &[Gesture], expected: f32) -> (f32, f32) {
+        match *gestures {
+            [Gesture::Rotate {
+                center_x,
+                center_y,
+                angle,
+            }] => {
+                assert!((angle - expected).abs() < 1e-5, "{}", angle);
+                (center_x, center_y)
+            }
+            _ => panic!("{:?}", gestures),
+        }
+    }
+
+    #[test]
+    fn tap_then_double_tap() {
+        let _context = fresh_context();
+        let mut gestures = GestureRecognizer::new(GestureConfig::default());
+
+        touch_event(TouchPhase::Started, 0, 10., 10.);
+        touch_event(TouchPhase::Ended, 0, 11., 10.);
+        assert_eq!(gestures.update(0.1), [Gesture::Tap { x: 11., y: 10. }]);
+
+        touch_event(TouchPhase::Started, 1, 10., 10.);
+        assert_eq!(gestures.update(0.2), []);
+        touch_event(TouchPhase::Ended, 1, 10., 10.);
+        assert_eq!(
+            gestures.update(0.3),
+            [Gesture::DoubleTap { x: 10., y: 10. }]
+        );
+    }
+
+    #[test]
+    fn long_press_and_swipe() {
+        let _context = fresh_context();
+        let mut gestures = GestureRecognizer::new(GestureConfig::default());
+
+        touch_event(TouchPhase::Started, 0, 10., 10.);
+        assert_eq!(gestures.update(0.0), []);
+        assert_eq!(
+            gestures.update(1.0),
+            [Gesture::LongPress { x: 10., y: 10. }]
+        );
+        touch_event(TouchPhase::Ended, 0, 10., 10.);
+        assert_eq!(gestures.update(1.1), []);
+
+        touch_event(TouchPhase::Started, 1, 100., 100.);
+        assert_eq!(gestures.update(2.0), []);
+        touch_event(TouchPhase::Moved, 1, 100., 50.);
+        touch_event(TouchPhase::Ended, 1, 100., 20.);
+        assert_eq!(
+            gestures.update(2.2),
+            [Gesture::Swipe {
+                start_x: 100.,
+                start_y: 100.,
+                end_x: 100.,
+                end_y: 20.,
+                direction: SwipeDirection::Up,
+            }]
+        );
+    }
+
+    #[test]
+    fn pinch_without_taps() {
+        let _context = fresh_context();
+        let mut gestures = GestureRecognizer::new(GestureConfig::default());
+
+        touch_event(TouchPhase::Started, 0, 0., 0.);
+        touch_event(TouchPhase::Started, 1, 100., 0.);
+        touch_event(TouchPhase::Moved, 1, 200., 0.);
+        assert_eq!(
+            gestures.update(0.1),
+            [Gesture::Pinch {
+                center_x: 100.,
+                center_y: 0.,
+                scale: 2.,
+            }]
+        );
+        touch_event(TouchPhase::Ended, 0, 0., 0.);
+        touch_event(TouchPhase::Ended, 1, 200., 0.);
+        assert_eq!(gestures.update(0.2), []);
+    }
+
+    #[test]
+    fn rotate_across_the_half_turn() {
+        let _context = fresh_context();
+        let mut gestures = GestureRecognizer::new(GestureConfig::default());
+
+        // the second finger starts left of the first one, at an angle of PI
+        touch_event(TouchPhase::Started, 0, 100., 100.);
+        touch_event(TouchPhase::Started, 1, 0., 100.);
+        // below the threshold
+        touch_event(TouchPhase::Moved, 1, 0., 105.);
+        assert_eq!(gestures.update(0.1), []);
+        // crossing over to -PI is a small turn, not almost a full one
+        touch_event(TouchPhase::Moved, 1, 0., 80.);
+        let angle = (20f32 / 100.).atan();
+        assert_eq!(rotation(&gestures.update(0.2), angle), (50., 90.));
+        touch_event(TouchPhase::Ended, 0, 100., 100.);
+        touch_event(TouchPhase::Ended, 1, 0., 80.);
+        assert_eq!(gestures.update(0.3), []);
+
+        // and back the other way, from just below -PI to just above PI
+        touch_event(TouchPhase::Started, 2, 100., 100.);
+        touch_event(TouchPhase::Started, 3, 0., 90.);
+        touch_event(TouchPhase::Moved, 3, 0., 110.);
+        let angle = -2. * (10f32 / 100.).atan();
+        assert_eq!(rotation(&gestures.update(0.4), angle), (50., 105.));
+    }
+
+    #[test]
+    fn simulated_mouse_clicks() {
+        let _context = fresh_context();
+        set_touch_mouse_simulation(TouchMouseSimulation::Off);
+        let mut gestures = GestureRecognizer::new(GestureConfig {
+            simulate_mouse: true,
+            ..GestureConfig::default()
+        });
+
+        touch_event(TouchPhase::Started, 0, 10., 10.);
+        touch_event(TouchPhase::Ended, 0, 10., 10.);
+        assert_eq!(gestures.update(0.1), [Gesture::Tap { x: 10., y: 10. }]);
+        assert!(is_mouse_button_pressed(MouseButton::Left));
+        assert!(!is_mouse_button_down(MouseButton::Left));
+        // the clicks are not fed back into the recognizer
+        assert_eq!(gestures.update(0.2), []);
+        end_frame();
+
+        touch_event(TouchPhase::Started, 1, 10., 10.);
+        assert_eq!(gestures.update(0.3), []);
+        assert_eq!(
+            gestures.update(1.0),
+            [Gesture::LongPress { x: 10., y: 10. }]
+        );
+        assert!(is_mouse_button_pressed(MouseButton::Right));
+        assert!(!is_mouse_button_pressed(MouseButton::Left));
+    }
+}
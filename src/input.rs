@@ -0,0 +1,716 @@
+//! Input events and their subscribers
+
+use crate::replay::{without_recording, InputCall};
+use crate::{
+    context_generation, mouse_button_up_event, try_with_context, with_context, with_context_ref,
+    ContextError,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum MouseButton {
+    Right,
+    Left,
+    Middle,
+    Unknown,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum TouchPhase {
+    Started,
+    Moved,
+    Ended,
+    Cancelled,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum KeyCode {
+    Space,
+    Apostrophe,
+    Comma,
+    Minus,
+    Period,
+    Slash,
+    Key0,
+    Key1,
+    Key2,
+    Key3,
+    Key4,
+    Key5,
+    Key6,
+    Key7,
+    Key8,
+    Key9,
+    Semicolon,
+    Equal,
+    A,
+    B,
+    C,
+    D,
+    E,
+    F,
+    G,
+    H,
+    I,
+    J,
+    K,
+    L,
+    M,
+    N,
+    O,
+    P,
+    Q,
+    R,
+    S,
+    T,
+    U,
+    V,
+    W,
+    X,
+    Y,
+    Z,
+    LeftBracket,
+    Backslash,
+    RightBracket,
+    GraveAccent,
+    Escape,
+    Enter,
+    Tab,
+    Backspace,
+    Insert,
+    Delete,
+    Right,
+    Left,
+    Down,
+    Up,
+    PageUp,
+    PageDown,
+    Home,
+    End,
+    CapsLock,
+    F1,
+    F2,
+    F3,
+    F4,
+    F5,
+    F6,
+    F7,
+    F8,
+    F9,
+    F10,
+    F11,
+    F12,
+    LeftShift,
+    LeftControl,
+    LeftAlt,
+    LeftSuper,
+    RightShift,
+    RightControl,
+    RightAlt,
+    RightSuper,
+    Unknown,
+}
+
+/// A finger on the screen, see [TouchPhase]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Touch {
+    /// Stays the same from [TouchPhase::Started] to [TouchPhase::Ended] or
+    /// [TouchPhase::Cancelled]
+    pub id: u64,
+    pub phase: TouchPhase,
+    pub x: f32,
+    pub y: f32,
+}
+
+impl Touch {
+    /// Whether the finger is still on the screen
+    pub fn is_active(&self) -> bool {
+        matches!(self.phase, TouchPhase::Started | TouchPhase::Moved)
+    }
+}
+
+/// How touches are turned into mouse events, see
+/// [set_touch_mouse_simulation()]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum TouchMouseSimulation {
+    /// Touches produce no mouse events
+    Off,
+    /// The first finger to touch down moves the mouse and holds the left
+    /// button until it lifts. Other fingers are ignored, even once the first
+    /// one lifted, until all simulated fingers are up.
+    #[default]
+    PrimaryTouchOnly,
+    /// Every finger moves the mouse. The left button is pressed when the
+    /// first finger touches down and released when the last one lifts.
+    /// Switching fingers moves the mouse without a
+    /// [mouse_delta()](crate::input::mouse_delta).
+    AllTouches,
+}
+
+/// Modifier keys held during a key or char event
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub struct KeyMods {
+    pub shift: bool,
+    pub ctrl: bool,
+    pub alt: bool,
+    pub logo: bool,
+}
+
+/// An input event as delivered by the platform, see [InputSubscriber]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum MiniquadInputEvent {
+    /// Not sent while the cursor is grabbed, see [RawMouseMotion]
+    ///
+    /// [RawMouseMotion]: MiniquadInputEvent::RawMouseMotion
+    MouseMotion {
+        x: f32,
+        y: f32,
+    },
+    /// Movement while the cursor is grabbed, see [set_cursor_grab()]
+    RawMouseMotion {
+        dx: f32,
+        dy: f32,
+    },
+    MouseWheel {
+        x: f32,
+        y: f32,
+    },
+    MouseButtonDown {
+        x: f32,
+        y: f32,
+        btn: MouseButton,
+    },
+    MouseButtonUp {
+        x: f32,
+        y: f32,
+        btn: MouseButton,
+    },
+    Char {
+        character: char,
+        modifiers: KeyMods,
+        repeat: bool,
+    },
+    KeyDown {
+        keycode: KeyCode,
+        modifiers: KeyMods,
+        repeat: bool,
+    },
+    KeyUp {
+        keycode: KeyCode,
+        modifiers: KeyMods,
+    },
+    Touch {
+        phase: TouchPhase,
+        id: u64,
+        x: f32,
+        y: f32,
+    },
+    WindowResized {
+        width: f32,
+        height: f32,
+    },
+}
+
+/// Per-subscriber event queues, stored in the `Context`
+#[derive(Debug, Default)]
+pub(crate) struct InputEvents {
+    /// Indexed by [InputSubscriber] id, `None` once unsubscribed
+    queues: Vec<Option<Vec<MiniquadInputEvent>>>,
+}
+
+impl InputEvents {
+    /// Append `event` to the queue of every subscriber
+    pub(crate) fn push(&mut self, event: MiniquadInputEvent) {
+        self.queues
+            .iter_mut()
+            .flatten()
+            .for_each(|queue| queue.push(event));
+    }
+
+    /// Drop the oldest events of queues longer than `max`, returns the
+    /// number of dropped events
+    pub(crate) fn truncate(&mut self, max: usize) -> usize {
+        self.queues
+            .iter_mut()
+            .flatten()
+            .map(|queue| {
+                let excess = queue.len().saturating_sub(max);
+                queue.drain(..excess);
+                excess
+            })
+            .sum()
+    }
+
+    fn subscribe(&mut self) -> usize {
+        match self.queues.iter().position(Option::is_none) {
+            Some(id) => {
+                self.queues[id] = Some(vec![]);
+                id
+            }
+            None => {
+                self.queues.push(Some(vec![]));
+                self.queues.len() - 1
+            }
+        }
+    }
+
+    fn unsubscribe(&mut self, id: usize) {
+        self.queues[id] = None;
+    }
+
+    fn drain(&mut self, id: usize) -> Vec<MiniquadInputEvent> {
+        self.queues[id]
+            .as_mut()
+            .map(std::mem::take)
+            .unwrap_or_default()
+    }
+}
+
+/// Receives its own copy of every input event, so several consumers (UI,
+/// gameplay, ...) can each drain all events every frame.
+///
+/// Unsubscribes when dropped.
+#[derive(Debug)]
+pub struct InputSubscriber {
+    id: usize,
+    /// Generation of the `Context` the subscription lives in
+    generation: u64,
+}
+
+impl InputSubscriber {
+    /// Take the events received since the last call, oldest first.
+    ///
+    /// # Panics
+    /// On any [ContextError]
+    #[track_caller]
+    pub fn drain(&self) -> Vec<MiniquadInputEvent> {
+        match self.try_drain() {
+            Ok(events) => events,
+            Err(err) => panic!("{}", err),
+        }
+    }
+
+    /// Fallible version of [InputSubscriber::drain]
+    #[track_caller]
+    pub fn try_drain(&self) -> Result<Vec<MiniquadInputEvent>, ContextError> {
+        if self.generation != context_generation() {
+            return Err(ContextError::StaleHandle);
+        }
+        try_with_context(|context| context.input_events.drain(self.id))
+    }
+}
+
+impl Drop for InputSubscriber {
+    fn drop(&mut self) {
+        if self.generation != context_generation() {
+            return;
+        }
+        // Nothing to do if the Context is gone; if it is borrowed the slot
+        // stays allocated until the Context is shut down
+        let _ = try_with_context(|context| context.input_events.unsubscribe(self.id));
+    }
+}
+
+/// Start receiving input events, see [InputSubscriber].
+///
+/// # Panics
+/// On any [ContextError]
+#[track_caller]
+pub fn register_input_subscriber() -> InputSubscriber {
+    match try_register_input_subscriber() {
+        Ok(subscriber) => subscriber,
+        Err(err) => panic!("{}", err),
+    }
+}
+
+/// Fallible version of [register_input_subscriber()]
+#[track_caller]
+pub fn try_register_input_subscriber() -> Result<InputSubscriber, ContextError> {
+    try_with_context(|context| InputSubscriber {
+        id: context.input_events.subscribe(),
+        generation: context_generation(),
+    })
+}
+
+/// Whether `btn` is held down.
+///
+/// # Panics
+/// On any [ContextError]
+#[track_caller]
+pub fn is_mouse_button_down(btn: MouseButton) -> bool {
+    with_context_ref(|context| context.mouse_down.contains(&btn))
+}
+
+/// Whether `btn` was pressed during the current frame.
+///
+/// # Panics
+/// On any [ContextError]
+#[track_caller]
+pub fn is_mouse_button_pressed(btn: MouseButton) -> bool {
+    with_context_ref(|context| context.mouse_pressed.contains(&btn))
+}
+
+/// Whether `btn` was released during the current frame.
+///
+/// # Panics
+/// On any [ContextError]
+#[track_caller]
+pub fn is_mouse_button_released(btn: MouseButton) -> bool {
+    with_context_ref(|context| context.mouse_released.contains(&btn))
+}
+
+/// Mouse position in logical pixels. Frozen while the cursor is grabbed,
+/// see [set_cursor_grab()].
+///
+/// # Panics
+/// On any [ContextError]
+#[track_caller]
+pub fn mouse_position() -> (f32, f32) {
+    with_context_ref(|context| (context.mouse_x, context.mouse_y))
+}
+
+/// How far the mouse moved during the current frame, also while the cursor
+/// is grabbed.
+///
+/// # Panics
+/// On any [ContextError]
+#[track_caller]
+pub fn mouse_delta() -> (f32, f32) {
+    with_context_ref(|context| (context.mouse_delta_x, context.mouse_delta_y))
+}
+
+/// Wheel scrolling during the current frame.
+///
+/// # Panics
+/// On any [ContextError]
+#[track_caller]
+pub fn mouse_wheel() -> (f32, f32) {
+    with_context_ref(|context| (context.mouse_wheel_x, context.mouse_wheel_y))
+}
+
+/// Simulated pointer lock: while grabbed, [mouse_position()] stays where it
+/// was and movement is only reported by [mouse_delta()], and to subscribers
+/// as [MiniquadInputEvent::RawMouseMotion]. Button events carry the frozen
+/// position. When released, the position jumps to where the platform last
+/// reported the cursor.
+///
+/// # Panics
+/// On any [ContextError]
+#[track_caller]
+pub fn set_cursor_grab(grab: bool) {
+    with_context(|context| {
+        context.record(InputCall::SetCursorGrab { grab });
+        context.cursor_grabbed = grab;
+        if let (false, Some((x, y))) = (grab, context.reported_mouse) {
+            context.mouse_x = x;
+            context.mouse_y = y;
+        }
+    });
+}
+
+/// # Panics
+/// On any [ContextError]
+#[track_caller]
+pub fn is_cursor_grabbed() -> bool {
+    with_context_ref(|context| context.cursor_grabbed)
+}
+
+/// Touches active during the current frame, ordered by id. Touches that
+/// ended during the frame are included with [TouchPhase::Ended] or
+/// [TouchPhase::Cancelled].
+///
+/// # Panics
+/// On any [ContextError]
+#[track_caller]
+pub fn touches() -> Vec<Touch> {
+    with_context_ref(|context| context.touches.values().copied().collect())
+}
+
+/// Change how touches are turned into mouse events.
+///
+/// If fingers are holding the simulated left button, it is released.
+///
+/// # Panics
+/// On any [ContextError]
+#[track_caller]
+pub fn set_touch_mouse_simulation(policy: TouchMouseSimulation) {
+    let release = with_context(|context| {
+        context.record(InputCall::SetTouchMouseSimulation { policy });
+        context.touch_mouse_simulation = policy;
+        let simulating = !context.simulated_touches.is_empty();
+        context.simulated_touches.clear();
+        simulating.then_some((context.mouse_x, context.mouse_y))
+    });
+    if let Some((x, y)) = release {
+        without_recording(|| mouse_button_up_event(MouseButton::Left, x, y));
+    }
+}
+
+/// Current policy, see [set_touch_mouse_simulation()].
+///
+/// # Panics
+/// On any [ContextError]
+#[track_caller]
+pub fn touch_mouse_simulation() -> TouchMouseSimulation {
+    with_context_ref(|context| context.touch_mouse_simulation)
+}
+
+/// Whether `key` is held down.
+///
+/// # Panics
+/// On any [ContextError]
+#[track_caller]
+pub fn is_key_down(key: KeyCode) -> bool {
+    with_context_ref(|context| context.keys_down.contains(&key))
+}
+
+/// Whether `key` was pressed during the current frame. Key repeats do not
+/// count, see [is_key_repeated()].
+///
+/// # Panics
+/// On any [ContextError]
+#[track_caller]
+pub fn is_key_pressed(key: KeyCode) -> bool {
+    with_context_ref(|context| context.keys_pressed.contains(&key))
+}
+
+/// Whether the platform repeated `key` during the current frame because it
+/// is held down.
+///
+/// # Panics
+/// On any [ContextError]
+#[track_caller]
+pub fn is_key_repeated(key: KeyCode) -> bool {
+    with_context_ref(|context| context.keys_repeated.contains(&key))
+}
+
+/// Whether `key` was released during the current frame.
+///
+/// # Panics
+/// On any [ContextError]
+#[track_caller]
+pub fn is_key_released(key: KeyCode) -> bool {
+    with_context_ref(|context| context.keys_released.contains(&key))
+}
+
+/// Keys held down, in [KeyCode] order.
+///
+/// # Panics
+/// On any [ContextError]
+#[track_caller]
+pub fn get_keys_down() -> Vec<KeyCode> {
+    with_context_ref(|context| context.keys_down.iter().copied().collect())
+}
+
+/// Modifiers of the last key or char event.
+///
+/// # Panics
+/// On any [ContextError]
+#[track_caller]
+pub fn key_mods() -> KeyMods {
+    with_context_ref(|context| context.key_mods)
+}
+
+/// Take the oldest character typed during the current frame. Unread
+/// characters are dropped by `end_frame()`.
+///
+/// # Panics
+/// On any [ContextError]
+#[track_caller]
+pub fn get_char_pressed() -> Option<char> {
+    with_context(|context| context.chars_pressed.pop_front())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::{context, fresh_context};
+    use crate::{
+        char_event, end_frame, key_down_event, key_up_event, mouse_button_down_event,
+        mouse_button_up_event, mouse_motion_event, mouse_wheel_event, touch_event,
+    };
+
+    #[test]
+    fn subscribers_get_their_own_copy() {
+        let _context = fresh_context();
+        let ui = register_input_subscriber();
+        let gameplay = register_input_subscriber();
+        mouse_motion_event(1., 2.);
+        let late = register_input_subscriber();
+        mouse_wheel_event(0., 1.);
+
+        let motion = MiniquadInputEvent::MouseMotion { x: 1., y: 2. };
+        let wheel = MiniquadInputEvent::MouseWheel { x: 0., y: 1. };
+        assert_eq!(ui.drain(), [motion, wheel]);
+        assert_eq!(ui.drain(), []);
+        assert_eq!(gameplay.drain(), [motion, wheel]);
+        assert_eq!(late.drain(), [wheel]);
+    }
+
+    #[test]
+    fn unsubscribed_slots_are_reused() {
+        let _context = fresh_context();
+        let first = register_input_subscriber();
+        let second = register_input_subscriber();
+        drop(first);
+        mouse_motion_event(1., 2.);
+        assert!(context(|c| c.input_events.queues[0].is_none()));
+
+        let third = register_input_subscriber();
+        assert_eq!((second.id, third.id), (1, 0));
+        assert_eq!(third.drain(), []);
+        assert_eq!(context(|c| c.input_events.queues.len()), 2);
+    }
+
+    #[test]
+    fn stale_subscriber_is_detected() {
+        let _context = fresh_context();
+        let stale = register_input_subscriber();
+        unsafe { crate::reset_context() }.unwrap();
+        let current = register_input_subscriber();
+        mouse_motion_event(1., 2.);
+
+        assert_eq!(stale.try_drain(), Err(ContextError::StaleHandle));
+        // dropping it leaves the subscriber with the same id alone
+        drop(stale);
+        assert_eq!(current.drain().len(), 1);
+    }
+
+    #[test]
+    fn mouse_buttons_pressed_and_released_last_one_frame() {
+        let _context = fresh_context();
+        mouse_button_down_event(MouseButton::Left, 1., 2.);
+        assert!(is_mouse_button_down(MouseButton::Left));
+        assert!(is_mouse_button_pressed(MouseButton::Left));
+        assert!(!is_mouse_button_released(MouseButton::Left));
+        assert_eq!(mouse_position(), (1., 2.));
+
+        assert_eq!(end_frame().mouse_buttons_cleared, 1);
+        assert!(is_mouse_button_down(MouseButton::Left));
+        assert!(!is_mouse_button_pressed(MouseButton::Left));
+
+        // a click within one frame is both pressed and released
+        mouse_button_up_event(MouseButton::Left, 1., 2.);
+        mouse_button_down_event(MouseButton::Right, 1., 2.);
+        mouse_button_up_event(MouseButton::Right, 1., 2.);
+        assert!(!is_mouse_button_down(MouseButton::Left));
+        assert!(is_mouse_button_released(MouseButton::Left));
+        assert!(is_mouse_button_pressed(MouseButton::Right));
+        assert!(is_mouse_button_released(MouseButton::Right));
+
+        assert_eq!(end_frame().mouse_buttons_cleared, 3);
+        assert!(!is_mouse_button_released(MouseButton::Left));
+        assert!(!is_mouse_button_pressed(MouseButton::Right));
+        assert!(!is_mouse_button_released(MouseButton::Right));
+    }
+
+    #[test]
+    fn grabbed_cursor_only_reports_deltas() {
+        let _context = fresh_context();
+        let subscriber = register_input_subscriber();
+        mouse_motion_event(10., 10.);
+        // nothing to move from yet
+        assert_eq!(mouse_delta(), (0., 0.));
+
+        set_cursor_grab(true);
+        mouse_motion_event(15., 20.);
+        mouse_motion_event(20., 15.);
+        mouse_wheel_event(0., 1.);
+        mouse_button_down_event(MouseButton::Left, 20., 15.);
+        assert_eq!(mouse_position(), (10., 10.));
+        assert_eq!(mouse_delta(), (10., 5.));
+        assert_eq!(mouse_wheel(), (0., 1.));
+        assert_eq!(
+            subscriber.drain(),
+            [
+                MiniquadInputEvent::MouseMotion { x: 10., y: 10. },
+                MiniquadInputEvent::RawMouseMotion { dx: 5., dy: 10. },
+                MiniquadInputEvent::RawMouseMotion { dx: 5., dy: -5. },
+                MiniquadInputEvent::MouseWheel { x: 0., y: 1. },
+                MiniquadInputEvent::MouseButtonDown {
+                    x: 10.,
+                    y: 10.,
+                    btn: MouseButton::Left
+                },
+            ]
+        );
+
+        end_frame();
+        set_cursor_grab(false);
+        assert_eq!(mouse_position(), (20., 15.));
+        assert_eq!(mouse_delta(), (0., 0.));
+        assert_eq!(mouse_wheel(), (0., 0.));
+    }
+
+    #[test]
+    fn keyboard_state() {
+        let _context = fresh_context();
+        let shift = KeyMods {
+            shift: true,
+            ..Default::default()
+        };
+        key_down_event(KeyCode::LeftShift, shift, false);
+        key_down_event(KeyCode::A, shift, false);
+        char_event('A', shift, false);
+        key_down_event(KeyCode::A, shift, true);
+        char_event('A', shift, true);
+        assert!(is_key_down(KeyCode::A) && is_key_pressed(KeyCode::A));
+        assert!(is_key_repeated(KeyCode::A));
+        assert_eq!(get_keys_down(), [KeyCode::A, KeyCode::LeftShift]);
+        assert!(key_mods().shift);
+        assert_eq!(get_char_pressed(), Some('A'));
+
+        let report = end_frame();
+        assert_eq!(report.chars_dropped, 1);
+        assert_eq!(get_char_pressed(), None);
+        key_up_event(KeyCode::A, shift);
+        assert!(!is_key_down(KeyCode::A) && !is_key_pressed(KeyCode::A));
+        assert!(is_key_released(KeyCode::A));
+    }
+
+    #[test]
+    fn second_finger_does_not_steal_primary_touch() {
+        let _context = fresh_context();
+        touch_event(TouchPhase::Started, 0, 1., 1.);
+        touch_event(TouchPhase::Started, 1, 50., 50.);
+        touch_event(TouchPhase::Moved, 1, 60., 60.);
+        assert_eq!(context(|c| (c.mouse_x, c.mouse_y)), (1., 1.));
+
+        touch_event(TouchPhase::Ended, 1, 60., 60.);
+        assert!(is_mouse_button_down(MouseButton::Left));
+        touch_event(TouchPhase::Ended, 0, 2., 2.);
+        assert!(!is_mouse_button_down(MouseButton::Left));
+        assert_eq!(context(|c| (c.mouse_x, c.mouse_y)), (2., 2.));
+    }
+
+    #[test]
+    fn all_touches_hold_button_until_last_finger_lifts() {
+        let _context = fresh_context();
+        set_touch_mouse_simulation(TouchMouseSimulation::AllTouches);
+        touch_event(TouchPhase::Started, 0, 1., 1.);
+        touch_event(TouchPhase::Started, 1, 50., 50.);
+        assert_eq!(context(|c| (c.mouse_x, c.mouse_y)), (50., 50.));
+
+        // switching fingers is not a movement, but moving one is
+        assert_eq!(mouse_delta(), (0., 0.));
+        touch_event(TouchPhase::Moved, 0, 2., 3.);
+        touch_event(TouchPhase::Moved, 0, 4., 4.);
+        assert_eq!(mouse_delta(), (2., 1.));
+
+        touch_event(TouchPhase::Ended, 0, 1., 1.);
+        assert!(is_mouse_button_down(MouseButton::Left));
+        touch_event(TouchPhase::Cancelled, 1, 50., 50.);
+        assert!(!is_mouse_button_down(MouseButton::Left));
+    }
+
+    #[test]
+    fn turning_simulation_off_releases_button() {
+        let _context = fresh_context();
+        touch_event(TouchPhase::Started, 0, 1., 1.);
+        set_touch_mouse_simulation(TouchMouseSimulation::Off);
+        assert!(!is_mouse_button_down(MouseButton::Left));
+
+        touch_event(TouchPhase::Ended, 0, 1., 1.);
+        touch_event(TouchPhase::Started, 1, 1., 1.);
+        assert!(!is_mouse_button_down(MouseButton::Left));
+    }
+}
@@ -0,0 +1,1338 @@
+//! Simulates `get_internal_gl()` in macroquad
+//!
+//! The global [Context] lives in a [RacyCell] and is only reachable through
+//! [with_context()], the handles in [window] and the raw [get_context()].
+//! [resize_event()], [mouse_motion_event()] and [touch_event()] stand in for
+//! the platform callbacks that feed it.
+
+#[cfg(feature = "checked")]
+use std::cell::Cell;
+use std::cell::UnsafeCell;
+use std::collections::{BTreeMap, BTreeSet, VecDeque};
+use std::fmt;
+use std::ops::{Deref, DerefMut};
+#[cfg(feature = "checked")]
+use std::panic::Location;
+use std::sync::atomic::{AtomicIsize, AtomicU64, Ordering};
+use std::sync::{Mutex, MutexGuard, PoisonError};
+use std::thread::{self, ThreadId};
+
+use audio::AudioContext;
+use gamepad::Gamepads;
+use input::{
+    InputEvents, KeyCode, KeyMods, MiniquadInputEvent, MouseButton, Touch, TouchMouseSimulation,
+    TouchPhase,
+};
+use replay::{without_recording, ActiveRecording, InputCall};
+use window::WindowConstraints;
+
+pub mod audio;
+pub mod gamepad;
+pub mod gesture;
+pub mod input;
+pub mod replay;
+pub mod window;
+
+#[cfg(test)]
+mod test_utils;
+#[cfg(test)]
+mod ub_patterns;
+
+/// Cell type that should be preferred over a `static mut` is better to use in a
+/// `static`
+///
+/// Based on [@Nemo157 comment](issue-53639)
+/// [issue-53639]: https://github.com/rust-lang/rust/issues/53639#issuecomment-790091647
+///
+/// With the `checked` feature enabled, borrows taken through [RacyCell::borrow]
+/// and [RacyCell::borrow_mut] are tracked at runtime and conflicting borrows
+/// panic with the location of both borrows. Without it, the guards compile down
+/// to plain references.
+///
+/// Raw access is not covered: [RacyCell::get_ref_mut], the pointers of
+/// [RacyCell::get_ptr_mut] and so [get_context()] and
+/// [get_internal_gl()](window::get_internal_gl) only check that no guard is
+/// live when they are called. What they return has no scope the cell could
+/// track, so aliasing it with a later borrow is not detected. None of the
+/// `unsound_*` patterns of the `ub_patterns` tests panic with the feature,
+/// only Miri reports them.
+///
+/// # Safety
+///
+/// Mutable references must never alias (point to the same memory location).
+/// Any previous result from calling this method on a specific instance
+/// **must** be dropped before calling this function again. This applies
+/// even if the mutable refernces lives in the stack frame of another function.
+#[cfg_attr(not(feature = "checked"), repr(transparent))]
+#[derive(Debug)]
+pub struct RacyCell<T> {
+    value: UnsafeCell<T>,
+    #[cfg(feature = "checked")]
+    borrow: BorrowFlag,
+}
+
+impl<T> RacyCell<T> {
+    #[inline(always)]
+    pub const fn new(value: T) -> Self {
+        RacyCell {
+            value: UnsafeCell::new(value),
+            #[cfg(feature = "checked")]
+            borrow: BorrowFlag::new(),
+        }
+    }
+
+    /// Get a shared reference to the inner type.
+    ///
+    /// With the `checked` feature, panics if a [RacyRefMut] is live.
+    ///
+    /// # Safety
+    /// See [RacyCell]
+    #[track_caller]
+    #[inline(always)]
+    pub unsafe fn get_ref(&self) -> &T {
+        #[cfg(feature = "checked")]
+        self.borrow.assert_not_mut_borrowed();
+        &*self.value.get()
+    }
+
+    /// Get a mutable reference to the inner type.
+    /// Callers should try to restrict the lifetime as much as possible.
+    ///
+    /// Callers may want to convert this result to a `*mut T` immediately.
+    ///
+    /// With the `checked` feature, panics if a [RacyRef] or [RacyRefMut] is
+    /// live. The returned reference itself is not tracked; prefer
+    /// [RacyCell::borrow_mut] where the borrow has a scope.
+    ///
+    /// # Safety
+    /// See [RacyCell]
+    #[allow(clippy::mut_from_ref)]
+    #[track_caller]
+    #[inline(always)]
+    pub unsafe fn get_ref_mut(&self) -> &mut T {
+        #[cfg(feature = "checked")]
+        self.borrow.assert_not_borrowed();
+        &mut *self.value.get()
+    }
+
+    /// Get a shared reference to the inner type wrapped in a guard.
+    ///
+    /// With the `checked` feature, panics if a [RacyRefMut] is live.
+    ///
+    /// # Safety
+    /// See [RacyCell]
+    #[track_caller]
+    #[inline(always)]
+    pub unsafe fn borrow(&self) -> RacyRef<'_, T> {
+        RacyRef {
+            #[cfg(feature = "checked")]
+            _borrow: self.borrow.acquire_shared(),
+            value: &*self.value.get(),
+        }
+    }
+
+    /// Get a mutable reference to the inner type wrapped in a guard.
+    /// The borrow ends when the guard is dropped.
+    ///
+    /// With the `checked` feature, panics if any other guard is live.
+    ///
+    /// # Safety
+    /// See [RacyCell]
+    #[track_caller]
+    #[inline(always)]
+    pub unsafe fn borrow_mut(&self) -> RacyRefMut<'_, T> {
+        RacyRefMut {
+            #[cfg(feature = "checked")]
+            _borrow: self.borrow.acquire_mut(),
+            value: &mut *self.value.get(),
+        }
+    }
+
+    /// Get a const pointer to the inner type
+    ///
+    /// # Safety
+    /// See [RacyCell]
+    #[inline(always)]
+    pub unsafe fn get_ptr(&self) -> *const T {
+        self.value.get()
+    }
+
+    /// Get a mutable pointer to the inner type
+    ///
+    /// # Safety
+    /// See [RacyCell]
+    #[inline(always)]
+    pub unsafe fn get_ptr_mut(&self) -> *mut T {
+        self.value.get()
+    }
+}
+
+unsafe impl<T> Sync for RacyCell<T> {}
+
+/// Shared borrow of a [RacyCell], see [RacyCell::borrow]
+#[derive(Debug)]
+pub struct RacyRef<'a, T: ?Sized> {
+    value: &'a T,
+    #[cfg(feature = "checked")]
+    _borrow: BorrowGuard<'a>,
+}
+
+impl<'a, T: ?Sized> RacyRef<'a, T> {
+    /// Narrow the borrow to a component of the borrowed data
+    #[inline(always)]
+    pub fn map<U: ?Sized>(orig: Self, f: impl FnOnce(&T) -> &U) -> RacyRef<'a, U> {
+        let RacyRef {
+            value,
+            #[cfg(feature = "checked")]
+            _borrow,
+        } = orig;
+        RacyRef {
+            value: f(value),
+            #[cfg(feature = "checked")]
+            _borrow,
+        }
+    }
+}
+
+impl<T: ?Sized> Deref for RacyRef<'_, T> {
+    type Target = T;
+
+    #[inline(always)]
+    fn deref(&self) -> &T {
+        self.value
+    }
+}
+
+/// Mutable borrow of a [RacyCell], see [RacyCell::borrow_mut]
+#[derive(Debug)]
+pub struct RacyRefMut<'a, T: ?Sized> {
+    value: &'a mut T,
+    #[cfg(feature = "checked")]
+    _borrow: BorrowGuard<'a>,
+}
+
+impl<'a, T: ?Sized> RacyRefMut<'a, T> {
+    /// Narrow the borrow to a component of the borrowed data
+    #[inline(always)]
+    pub fn map<U: ?Sized>(orig: Self, f: impl FnOnce(&mut T) -> &mut U) -> RacyRefMut<'a, U> {
+        let RacyRefMut {
+            value,
+            #[cfg(feature = "checked")]
+            _borrow,
+        } = orig;
+        RacyRefMut {
+            value: f(value),
+            #[cfg(feature = "checked")]
+            _borrow,
+        }
+    }
+}
+
+impl<T: ?Sized> Deref for RacyRefMut<'_, T> {
+    type Target = T;
+
+    #[inline(always)]
+    fn deref(&self) -> &T {
+        self.value
+    }
+}
+
+impl<T: ?Sized> DerefMut for RacyRefMut<'_, T> {
+    #[inline(always)]
+    fn deref_mut(&mut self) -> &mut T {
+        self.value
+    }
+}
+
+/// Borrow state of a [RacyCell], only present with the `checked` feature
+#[cfg(feature = "checked")]
+#[derive(Debug)]
+struct BorrowFlag {
+    /// Number of live [RacyRef]s, or `-1` while a [RacyRefMut] is live
+    state: Cell<isize>,
+    /// Where the oldest live borrow was taken
+    location: Cell<Option<&'static Location<'static>>>,
+}
+
+#[cfg(feature = "checked")]
+impl BorrowFlag {
+    const fn new() -> Self {
+        BorrowFlag {
+            state: Cell::new(0),
+            location: Cell::new(None),
+        }
+    }
+
+    fn borrowed_at(&self) -> &'static Location<'static> {
+        self.location
+            .get()
+            .expect("borrowed RacyCell has no borrow location")
+    }
+
+    #[track_caller]
+    fn assert_not_mut_borrowed(&self) {
+        if self.state.get() < 0 {
+            panic!(
+                "RacyCell already mutably borrowed at {}",
+                self.borrowed_at()
+            );
+        }
+    }
+
+    #[track_caller]
+    fn assert_not_borrowed(&self) {
+        self.assert_not_mut_borrowed();
+        if self.state.get() > 0 {
+            panic!("RacyCell already borrowed at {}", self.borrowed_at());
+        }
+    }
+
+    #[track_caller]
+    fn acquire_shared(&self) -> BorrowGuard<'_> {
+        self.assert_not_mut_borrowed();
+        let state = self.state.get();
+        if state == 0 {
+            self.location.set(Some(Location::caller()));
+        }
+        self.state.set(state + 1);
+        BorrowGuard { flag: self }
+    }
+
+    #[track_caller]
+    fn acquire_mut(&self) -> BorrowGuard<'_> {
+        self.assert_not_borrowed();
+        self.location.set(Some(Location::caller()));
+        self.state.set(-1);
+        BorrowGuard { flag: self }
+    }
+}
+
+/// Releases a borrow of a [BorrowFlag] when dropped
+#[cfg(feature = "checked")]
+#[derive(Debug)]
+struct BorrowGuard<'a> {
+    flag: &'a BorrowFlag,
+}
+
+#[cfg(feature = "checked")]
+impl Drop for BorrowGuard<'_> {
+    fn drop(&mut self) {
+        let state = self.flag.state.get();
+        let state = if state < 0 { 0 } else { state - 1 };
+        if state == 0 {
+            self.flag.location.set(None);
+        }
+        self.flag.state.set(state);
+    }
+}
+
+#[no_mangle]
+static CONTEXT: RacyCell<Option<Context>> = RacyCell::new(None);
+
+/// Global state of the library, see [with_context()]
+#[derive(Debug)]
+pub struct Context {
+    // exposed to user via InternalGlContext
+    pub(crate) quad_context: u32,
+    pub(crate) gl: u32,
+    /// Whether the [GlToken](window::GlToken) was taken
+    pub(crate) gl_token_taken: bool,
+
+    pub(crate) frame: u64,
+
+    // only accessed directly from library
+    /// Logical size, after applying [Context::window_constraints]
+    pub(crate) screen_width: f32,
+    pub(crate) screen_height: f32,
+    /// Size in pixels as last reported by the platform
+    pub(crate) physical_width: f32,
+    pub(crate) physical_height: f32,
+    /// Physical pixels per logical pixel
+    pub(crate) dpi_scale: f32,
+    pub(crate) window_constraints: WindowConstraints,
+    pub(crate) mouse_x: f32,
+    pub(crate) mouse_y: f32,
+    /// Mouse position as last reported by the platform, differs from
+    /// `mouse_x` and `mouse_y` while the cursor is grabbed. `None` until the
+    /// first report, or when the next one must not count as movement.
+    pub(crate) reported_mouse: Option<(f32, f32)>,
+    /// Mouse movement during the current frame
+    pub(crate) mouse_delta_x: f32,
+    pub(crate) mouse_delta_y: f32,
+    /// Wheel scrolling during the current frame
+    pub(crate) mouse_wheel_x: f32,
+    pub(crate) mouse_wheel_y: f32,
+    /// Pointer lock: the position is frozen and only deltas are reported
+    pub(crate) cursor_grabbed: bool,
+    pub(crate) mouse_down: BTreeSet<MouseButton>,
+    /// Pressed since the end of the last frame
+    pub(crate) mouse_pressed: BTreeSet<MouseButton>,
+    /// Released since the end of the last frame
+    pub(crate) mouse_released: BTreeSet<MouseButton>,
+    pub(crate) keys_down: BTreeSet<KeyCode>,
+    /// Pressed since the end of the last frame, not counting repeats
+    pub(crate) keys_pressed: BTreeSet<KeyCode>,
+    /// Repeated by the platform since the end of the last frame
+    pub(crate) keys_repeated: BTreeSet<KeyCode>,
+    /// Released since the end of the last frame
+    pub(crate) keys_released: BTreeSet<KeyCode>,
+    /// Modifiers of the last key or char event
+    pub(crate) key_mods: KeyMods,
+    /// Characters typed during the current frame, not read yet
+    pub(crate) chars_pressed: VecDeque<char>,
+    /// Touches active during the current frame, keyed by id. Ended and
+    /// cancelled touches are kept until the end of the frame.
+    pub(crate) touches: BTreeMap<u64, Touch>,
+    pub(crate) touch_mouse_simulation: TouchMouseSimulation,
+    /// Touches currently simulating the left mouse button
+    pub(crate) simulated_touches: BTreeSet<u64>,
+    /// Touch that last moved the simulated mouse, `None` once it released
+    /// the button
+    pub(crate) mouse_touch: Option<u64>,
+    pub(crate) gamepads: Gamepads,
+    pub(crate) input_events: InputEvents,
+    /// Calls recorded since `start_recording()`
+    pub(crate) recording: Option<ActiveRecording>,
+    pub(crate) audio_context: AudioContext,
+}
+
+impl Default for Context {
+    fn default() -> Self {
+        Self {
+            quad_context: 0,
+            gl: 0,
+            gl_token_taken: false,
+            frame: 0,
+            screen_height: 0.0,
+            screen_width: 0.0,
+            physical_width: 0.0,
+            physical_height: 0.0,
+            dpi_scale: 1.0,
+            window_constraints: WindowConstraints::default(),
+            mouse_x: 0.0,
+            mouse_y: 0.0,
+            reported_mouse: None,
+            mouse_delta_x: 0.0,
+            mouse_delta_y: 0.0,
+            mouse_wheel_x: 0.0,
+            mouse_wheel_y: 0.0,
+            cursor_grabbed: false,
+            mouse_down: BTreeSet::new(),
+            mouse_pressed: BTreeSet::new(),
+            mouse_released: BTreeSet::new(),
+            keys_down: BTreeSet::new(),
+            keys_pressed: BTreeSet::new(),
+            keys_repeated: BTreeSet::new(),
+            keys_released: BTreeSet::new(),
+            key_mods: KeyMods::default(),
+            chars_pressed: VecDeque::new(),
+            touches: BTreeMap::new(),
+            touch_mouse_simulation: TouchMouseSimulation::default(),
+            simulated_touches: BTreeSet::new(),
+            mouse_touch: None,
+            gamepads: Gamepads::default(),
+            input_events: Default::default(),
+            recording: None,
+            audio_context: Default::default(),
+        }
+    }
+}
+
+/// Error returned when the `Context` cannot be accessed
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ContextError {
+    /// `init_context()` has not been called
+    NotInitialized,
+    /// The `Context` is borrowed by a [with_context()] closure, or by a
+    /// [with_context_ref()] closure when exclusive access was requested
+    AlreadyBorrowed,
+    /// The `Context` was accessed from another thread than the one that
+    /// initialized it
+    WrongThread {
+        initialized_on: ThreadInfo,
+        accessed_from: ThreadInfo,
+    },
+    /// `init_context()` was called while a `Context` already exists
+    AlreadyInitialized,
+    /// A handle such as [RawInternalGlContext](window::RawInternalGlContext) outlived the `Context` it was
+    /// created from
+    StaleHandle,
+}
+
+impl fmt::Display for ContextError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ContextError::NotInitialized => write!(
+                f,
+                "Context is not initialized, init_context() must be called first"
+            ),
+            ContextError::AlreadyBorrowed => write!(f, "Context is already borrowed"),
+            ContextError::WrongThread {
+                initialized_on,
+                accessed_from,
+            } => write!(
+                f,
+                "Context accessed from thread {}, but it was initialized on thread {}",
+                accessed_from, initialized_on
+            ),
+            ContextError::AlreadyInitialized => write!(f, "Context is already initialized"),
+            ContextError::StaleHandle => write!(
+                f,
+                "handle refers to a Context that has been shut down or reset"
+            ),
+        }
+    }
+}
+
+impl std::error::Error for ContextError {}
+
+/// Identifies a thread in [ContextError::WrongThread]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ThreadInfo {
+    pub id: ThreadId,
+    pub name: Option<String>,
+}
+
+impl ThreadInfo {
+    fn current() -> Self {
+        let thread = thread::current();
+        ThreadInfo {
+            id: thread.id(),
+            name: thread.name().map(str::to_owned),
+        }
+    }
+}
+
+impl fmt::Display for ThreadInfo {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match &self.name {
+            Some(name) => write!(f, "'{}' ({:?})", name, self.id),
+            None => write!(f, "<unnamed> ({:?})", self.id),
+        }
+    }
+}
+
+/// Thread that called [init_context()], the only one allowed to access the
+/// `Context`
+static CONTEXT_THREAD: Mutex<Option<ThreadInfo>> = Mutex::new(None);
+
+/// Incremented whenever a `Context` is created or destroyed, so handles into
+/// an old `Context` can be told apart from handles into the current one
+static CONTEXT_GENERATION: AtomicU64 = AtomicU64::new(0);
+
+/// Current value of [CONTEXT_GENERATION]
+pub(crate) fn context_generation() -> u64 {
+    CONTEXT_GENERATION.load(Ordering::Acquire)
+}
+
+/// Number of running [with_context_ref()] closures, or `-1` while a
+/// [with_context()] closure is running
+static CONTEXT_BORROW: AtomicIsize = AtomicIsize::new(0);
+
+/// Whether a [with_context()] or [with_context_ref()] closure is running
+fn context_borrowed() -> bool {
+    CONTEXT_BORROW.load(Ordering::Acquire) != 0
+}
+
+/// A borrow counted in [CONTEXT_BORROW], released when dropped, including
+/// when unwinding
+struct ContextBorrow {
+    shared: bool,
+}
+
+impl ContextBorrow {
+    fn exclusive() -> Result<Self, ContextError> {
+        CONTEXT_BORROW
+            .compare_exchange(0, -1, Ordering::Acquire, Ordering::Relaxed)
+            .map(|_| ContextBorrow { shared: false })
+            .map_err(|_| ContextError::AlreadyBorrowed)
+    }
+
+    fn shared() -> Result<Self, ContextError> {
+        let mut borrows = CONTEXT_BORROW.load(Ordering::Relaxed);
+        loop {
+            if borrows < 0 {
+                return Err(ContextError::AlreadyBorrowed);
+            }
+            match CONTEXT_BORROW.compare_exchange_weak(
+                borrows,
+                borrows + 1,
+                Ordering::Acquire,
+                Ordering::Relaxed,
+            ) {
+                Ok(_) => return Ok(ContextBorrow { shared: true }),
+                Err(current) => borrows = current,
+            }
+        }
+    }
+}
+
+impl Drop for ContextBorrow {
+    fn drop(&mut self) {
+        if self.shared {
+            CONTEXT_BORROW.fetch_sub(1, Ordering::Release);
+        } else {
+            CONTEXT_BORROW.store(0, Ordering::Release);
+        }
+    }
+}
+
+/// Thread recorded by [init_context()]. It is only `Some` while a `Context`
+/// exists, and is only changed while holding both this lock and an exclusive
+/// [ContextBorrow].
+fn context_thread() -> MutexGuard<'static, Option<ThreadInfo>> {
+    CONTEXT_THREAD
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner)
+}
+
+/// Fails if the `Context` was initialized on another thread than the
+/// current one, or does not exist.
+///
+/// Without a `Context` no thread is allowed, so a worker thread cannot reach
+/// `CONTEXT` while the main thread is initializing it.
+fn check_context_thread() -> Result<(), ContextError> {
+    match &*context_thread() {
+        None => Err(ContextError::NotInitialized),
+        Some(initialized_on) => check_thread(initialized_on),
+    }
+}
+
+/// Fails if `initialized_on` is not the current thread
+fn check_thread(initialized_on: &ThreadInfo) -> Result<(), ContextError> {
+    if initialized_on.id == thread::current().id() {
+        Ok(())
+    } else {
+        Err(ContextError::WrongThread {
+            initialized_on: initialized_on.clone(),
+            accessed_from: ThreadInfo::current(),
+        })
+    }
+}
+
+/// # Safety
+/// Requirements:
+/// - this function must only be called from the "main" thread
+/// - no reference obtained from [get_context()] may be live
+///
+/// The calling thread is recorded and every later access to the `Context`
+/// from another thread fails with [ContextError::WrongThread].
+pub unsafe fn init_context() -> Result<(), ContextError> {
+    let mut thread = context_thread();
+    if let Some(initialized_on) = &*thread {
+        check_thread(initialized_on)?;
+        return Err(ContextError::AlreadyInitialized);
+    }
+    let _borrow = ContextBorrow::exclusive()?;
+    *CONTEXT.borrow_mut() = Some(Context::default());
+    *thread = Some(ThreadInfo::current());
+    CONTEXT_GENERATION.fetch_add(1, Ordering::AcqRel);
+    Ok(())
+}
+
+/// Drop the `Context`.
+///
+/// Handles such as [RawInternalGlContext](window::RawInternalGlContext) created before this call become stale:
+/// using them fails with [ContextError::StaleHandle] instead of touching freed
+/// memory. Afterwards `init_context()` may be called again, from any thread.
+///
+/// # Safety
+/// Requirements:
+/// - this function must only be called from the "main" thread
+/// - no reference obtained from [get_context()] may be live, including
+///   through the raw pointers of a [RawInternalGlContext](window::RawInternalGlContext)
+pub unsafe fn shutdown_context() -> Result<(), ContextError> {
+    let mut thread = context_thread();
+    check_thread(thread.as_ref().ok_or(ContextError::NotInitialized)?)?;
+    let _borrow = ContextBorrow::exclusive()?;
+    *thread = None;
+    *CONTEXT.borrow_mut() = None;
+    CONTEXT_GENERATION.fetch_add(1, Ordering::AcqRel);
+    Ok(())
+}
+
+/// Replace the `Context` with a fresh one, see [shutdown_context()].
+///
+/// # Safety
+/// Same as [shutdown_context()]
+pub unsafe fn reset_context() -> Result<(), ContextError> {
+    shutdown_context()?;
+    init_context()
+}
+
+/// Fallible version of [get_context()]
+///
+/// With the `checked` feature, fails if a [with_context()] borrow is live
+/// and panics if a [RacyCell] guard is, but the returned pointer is not
+/// tracked: see [RacyCell].
+///
+/// # Safety
+/// Requirements:
+/// - no reference obtained from a previous call may be live when the result
+///   is dereferenced
+#[track_caller]
+pub unsafe fn try_get_context() -> Result<*mut Context, ContextError> {
+    check_context_thread()?;
+    if context_borrowed() {
+        return Err(ContextError::AlreadyBorrowed);
+    }
+    match CONTEXT.get_ref_mut() {
+        None => Err(ContextError::NotInitialized),
+        Some(x) => Ok(x),
+    }
+}
+
+/// # Safety
+/// Requirements:
+/// - `init_context()` must be called before this function.
+/// - this function must only be called from the "main" thread
+///
+/// # Panics
+/// On any [ContextError], see [try_get_context()]
+#[track_caller]
+pub unsafe fn get_context() -> *mut Context {
+    match try_get_context() {
+        Ok(context) => context,
+        Err(err) => panic!("{}", err),
+    }
+}
+
+/// Run `f` with exclusive access to the `Context`.
+///
+/// The `&mut Context` cannot escape `f`, and nested calls (directly or through
+/// another event handler called from `f`) are detected, so library code built
+/// on this function needs no `unsafe`.
+///
+/// With the `checked` feature, the borrow is also tracked by the [RacyCell],
+/// so a [get_context()] inside `f` panics as well.
+#[track_caller]
+pub fn try_with_context<R>(f: impl FnOnce(&mut Context) -> R) -> Result<R, ContextError> {
+    check_context_thread()?;
+    let _borrow = ContextBorrow::exclusive()?;
+
+    // SAFETY: CONTEXT_BORROW guarantees no other `with_context()` or
+    // `with_context_ref()` borrow is live, and check_context_thread() that we
+    // are on the context thread
+    let mut context = unsafe { CONTEXT.borrow_mut() };
+    match &mut *context {
+        None => Err(ContextError::NotInitialized),
+        Some(context) => Ok(f(context)),
+    }
+}
+
+/// Panicking version of [try_with_context()]
+///
+/// # Panics
+/// On any [ContextError]
+#[track_caller]
+pub fn with_context<R>(f: impl FnOnce(&mut Context) -> R) -> R {
+    match try_with_context(f) {
+        Ok(result) => result,
+        Err(err) => panic!("{}", err),
+    }
+}
+
+/// Run `f` with shared access to the `Context`.
+///
+/// Unlike [try_with_context()], calls may be nested, so read-only queries
+/// can be called from inside `f`. Anything needing exclusive access fails
+/// with [ContextError::AlreadyBorrowed] while `f` runs.
+#[track_caller]
+pub fn try_with_context_ref<R>(f: impl FnOnce(&Context) -> R) -> Result<R, ContextError> {
+    check_context_thread()?;
+    let _borrow = ContextBorrow::shared()?;
+
+    // SAFETY: CONTEXT_BORROW guarantees no `with_context()` borrow is live,
+    // and check_context_thread() that we are on the context thread
+    let context = unsafe { CONTEXT.borrow() };
+    match &*context {
+        None => Err(ContextError::NotInitialized),
+        Some(context) => Ok(f(context)),
+    }
+}
+
+/// Panicking version of [try_with_context_ref()]
+///
+/// # Panics
+/// On any [ContextError]
+#[track_caller]
+pub fn with_context_ref<R>(f: impl FnOnce(&Context) -> R) -> R {
+    match try_with_context_ref(f) {
+        Ok(result) => result,
+        Err(err) => panic!("{}", err),
+    }
+}
+
+impl Context {
+    pub(crate) fn perform_render_passes(&mut self) {
+        self.quad_context += 1;
+        self.gl += 1;
+    }
+
+    /// Mouse event to simulate for a touch event, according to
+    /// [Context::touch_mouse_simulation].
+    ///
+    /// When the event comes from another finger than the previous one, or
+    /// from the first finger after the button was released, the mouse jumps
+    /// without reporting the jump as a delta.
+    fn simulated_mouse_event(&mut self, phase: TouchPhase, id: u64) -> Option<SimulatedMouse> {
+        let event = self.policy_mouse_event(phase, id);
+        if event.is_some() && self.mouse_touch != Some(id) {
+            self.reported_mouse = None;
+        }
+        match event {
+            Some(SimulatedMouse::ButtonUp) => self.mouse_touch = None,
+            Some(_) => self.mouse_touch = Some(id),
+            None => {}
+        }
+        event
+    }
+
+    fn policy_mouse_event(&mut self, phase: TouchPhase, id: u64) -> Option<SimulatedMouse> {
+        let policy = self.touch_mouse_simulation;
+        if policy == TouchMouseSimulation::Off {
+            return None;
+        }
+        match phase {
+            TouchPhase::Started => {
+                let first = self.simulated_touches.is_empty();
+                if !first && policy == TouchMouseSimulation::PrimaryTouchOnly {
+                    return None;
+                }
+                self.simulated_touches.insert(id);
+                Some(if first {
+                    SimulatedMouse::ButtonDown
+                } else {
+                    SimulatedMouse::Motion
+                })
+            }
+            TouchPhase::Moved => self
+                .simulated_touches
+                .contains(&id)
+                .then_some(SimulatedMouse::Motion),
+            TouchPhase::Ended | TouchPhase::Cancelled => {
+                let last = self.simulated_touches.remove(&id) && self.simulated_touches.is_empty();
+                last.then_some(SimulatedMouse::ButtonUp)
+            }
+        }
+    }
+
+    /// Recompute the logical screen size from the physical one, the DPI
+    /// scale and the window constraints
+    pub(crate) fn update_screen_size(&mut self) {
+        let (width, height) = self.window_constraints.apply(
+            self.physical_width / self.dpi_scale,
+            self.physical_height / self.dpi_scale,
+        );
+        self.screen_width = width;
+        self.screen_height = height;
+        self.input_events
+            .push(MiniquadInputEvent::WindowResized { width, height });
+    }
+
+    /// The platform reported the cursor at `x`, `y`. The first report is not
+    /// a movement, there is nothing to move from.
+    /// Returns the movement since the last reported position, if any
+    pub(crate) fn move_mouse(&mut self, x: f32, y: f32) -> Option<(f32, f32)> {
+        let delta = self
+            .reported_mouse
+            .map(|(reported_x, reported_y)| (x - reported_x, y - reported_y));
+        if let Some((dx, dy)) = delta {
+            self.mouse_delta_x += dx;
+            self.mouse_delta_y += dy;
+        }
+        self.reported_mouse = Some((x, y));
+        if !self.cursor_grabbed {
+            self.mouse_x = x;
+            self.mouse_y = y;
+        }
+        delta
+    }
+
+    /// Add `call` to the recording in progress, if any
+    pub(crate) fn record(&mut self, call: InputCall) {
+        if let Some(recording) = &mut self.recording {
+            recording.push(self.frame, call);
+        }
+    }
+
+    pub(crate) fn begin_frame(&mut self) {
+        self.frame += 1;
+    }
+
+    /// Reset the input state that only lasts for one frame
+    pub(crate) fn end_frame(&mut self) -> FrameReport {
+        let touches_before = self.touches.len();
+        self.touches.retain(|_, touch| touch.is_active());
+
+        let report = FrameReport {
+            touches_pruned: touches_before - self.touches.len(),
+            mouse_buttons_cleared: self.mouse_pressed.len() + self.mouse_released.len(),
+            keys_cleared: self.keys_pressed.len()
+                + self.keys_repeated.len()
+                + self.keys_released.len(),
+            chars_dropped: self.chars_pressed.len(),
+            gamepad_buttons_cleared: self.gamepads.end_frame(),
+            input_events_dropped: self.input_events.truncate(MAX_QUEUED_INPUT_EVENTS),
+        };
+        self.mouse_pressed.clear();
+        self.mouse_released.clear();
+        self.keys_pressed.clear();
+        self.keys_repeated.clear();
+        self.keys_released.clear();
+        self.chars_pressed.clear();
+        self.mouse_delta_x = 0.0;
+        self.mouse_delta_y = 0.0;
+        self.mouse_wheel_x = 0.0;
+        self.mouse_wheel_y = 0.0;
+        report
+    }
+}
+
+/// Input events kept per subscriber across frames, older events are dropped
+/// by [end_frame()]
+pub const MAX_QUEUED_INPUT_EVENTS: usize = 1024;
+
+/// What [end_frame()] discarded
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct FrameReport {
+    /// Ended or cancelled touches removed from the active touches
+    pub touches_pruned: usize,
+    /// Entries of the pressed and released mouse button sets
+    pub mouse_buttons_cleared: usize,
+    /// Entries of the pressed, repeated and released key sets
+    pub keys_cleared: usize,
+    /// Typed characters not read with `get_char_pressed()`
+    pub chars_dropped: usize,
+    /// Entries of the pressed and released button sets of all gamepads
+    pub gamepad_buttons_cleared: usize,
+    /// Events not drained by subscribers in time, see
+    /// [MAX_QUEUED_INPUT_EVENTS]
+    pub input_events_dropped: usize,
+}
+
+/// Called by the game loop before processing the input of a frame.
+///
+/// # Panics
+/// On any [ContextError]
+#[track_caller]
+pub fn begin_frame() {
+    with_context(|ctx| {
+        ctx.record(InputCall::BeginFrame);
+        ctx.begin_frame();
+    });
+}
+
+/// Called by the game loop once all input of the frame has been processed,
+/// resets the state that only lasts for one frame.
+///
+/// # Panics
+/// On any [ContextError]
+#[track_caller]
+pub fn end_frame() -> FrameReport {
+    with_context(|ctx| {
+        ctx.record(InputCall::EndFrame);
+        ctx.end_frame()
+    })
+}
+
+/// Number of [begin_frame()] calls since the `Context` was initialized.
+///
+/// # Panics
+/// On any [ContextError]
+#[track_caller]
+pub fn frame_number() -> u64 {
+    with_context_ref(|ctx| ctx.frame)
+}
+
+/// `width` and `height` are in physical pixels. The logical size is derived
+/// from them with the DPI scale and limited by the [WindowConstraints].
+pub fn resize_event(width: f32, height: f32) {
+    with_context(|ctx| {
+        // This would be UB with a raw `&mut Context` because
+        // mouse_motion_event() mutates the underlying Context while ctx is
+        // live. with_context() panics instead.
+        //mouse_motion_event(0., 0.);
+
+        ctx.record(InputCall::Resize { width, height });
+        ctx.physical_height = height;
+        ctx.physical_width = width;
+        ctx.update_screen_size();
+    });
+    // This is fine because the ctx borrow ended with the closure
+    //mouse_motion_event(0., 0.);
+}
+
+/// Whether `scale` can be passed to [dpi_scale_event()]
+pub(crate) fn is_valid_dpi_scale(scale: f32) -> bool {
+    scale.is_finite() && scale > 0.0
+}
+
+/// The window moved to a display with another DPI scale
+///
+/// # Panics
+/// - if `scale` is not finite and positive
+/// - on any [ContextError]
+#[track_caller]
+pub fn dpi_scale_event(scale: f32) {
+    assert!(
+        is_valid_dpi_scale(scale),
+        "DPI scale must be finite and positive, got {}",
+        scale
+    );
+    with_context(|ctx| {
+        ctx.record(InputCall::DpiScale { scale });
+        ctx.dpi_scale = scale;
+        ctx.update_screen_size();
+    });
+}
+
+pub fn mouse_motion_event(x: f32, y: f32) {
+    with_context(|ctx| {
+        ctx.record(InputCall::MouseMotion { x, y });
+        let delta = ctx.move_mouse(x, y);
+        // the absolute position is frozen while grabbed
+        let event = match (ctx.cursor_grabbed, delta) {
+            (false, _) => Some(MiniquadInputEvent::MouseMotion { x, y }),
+            (true, Some((dx, dy))) => Some(MiniquadInputEvent::RawMouseMotion { dx, dy }),
+            (true, None) => None,
+        };
+        if let Some(event) = event {
+            ctx.input_events.push(event);
+        }
+    });
+}
+
+pub fn mouse_wheel_event(x: f32, y: f32) {
+    with_context(|ctx| {
+        ctx.record(InputCall::MouseWheel { x, y });
+        ctx.mouse_wheel_x += x;
+        ctx.mouse_wheel_y += y;
+        ctx.input_events
+            .push(MiniquadInputEvent::MouseWheel { x, y });
+    });
+}
+
+pub fn mouse_button_down_event(btn: MouseButton, x: f32, y: f32) {
+    with_context(|ctx| {
+        ctx.record(InputCall::MouseButtonDown { button: btn, x, y });
+        ctx.move_mouse(x, y);
+        ctx.mouse_down.insert(btn);
+        ctx.mouse_pressed.insert(btn);
+        let (x, y) = (ctx.mouse_x, ctx.mouse_y);
+        ctx.input_events
+            .push(MiniquadInputEvent::MouseButtonDown { x, y, btn });
+    });
+}
+
+pub fn mouse_button_up_event(btn: MouseButton, x: f32, y: f32) {
+    with_context(|ctx| {
+        ctx.record(InputCall::MouseButtonUp { button: btn, x, y });
+        ctx.move_mouse(x, y);
+        ctx.mouse_down.remove(&btn);
+        ctx.mouse_released.insert(btn);
+        let (x, y) = (ctx.mouse_x, ctx.mouse_y);
+        ctx.input_events
+            .push(MiniquadInputEvent::MouseButtonUp { x, y, btn });
+    });
+}
+
+pub fn key_down_event(keycode: KeyCode, modifiers: KeyMods, repeat: bool) {
+    with_context(|ctx| {
+        ctx.record(InputCall::KeyDown {
+            keycode,
+            modifiers,
+            repeat,
+        });
+        ctx.key_mods = modifiers;
+        ctx.keys_down.insert(keycode);
+        if repeat {
+            ctx.keys_repeated.insert(keycode);
+        } else {
+            ctx.keys_pressed.insert(keycode);
+        }
+        ctx.input_events.push(MiniquadInputEvent::KeyDown {
+            keycode,
+            modifiers,
+            repeat,
+        });
+    });
+}
+
+pub fn key_up_event(keycode: KeyCode, modifiers: KeyMods) {
+    with_context(|ctx| {
+        ctx.record(InputCall::KeyUp { keycode, modifiers });
+        ctx.key_mods = modifiers;
+        ctx.keys_down.remove(&keycode);
+        ctx.keys_released.insert(keycode);
+        ctx.input_events
+            .push(MiniquadInputEvent::KeyUp { keycode, modifiers });
+    });
+}
+
+pub fn char_event(character: char, modifiers: KeyMods, repeat: bool) {
+    with_context(|ctx| {
+        ctx.record(InputCall::Char {
+            character,
+            modifiers,
+            repeat,
+        });
+        ctx.key_mods = modifiers;
+        ctx.chars_pressed.push_back(character);
+        ctx.input_events.push(MiniquadInputEvent::Char {
+            character,
+            modifiers,
+            repeat,
+        });
+    });
+}
+
+/// Mouse event simulated from a touch, see [TouchMouseSimulation]
+enum SimulatedMouse {
+    ButtonDown,
+    Motion,
+    ButtonUp,
+}
+
+/// Since we call another "macroquad" functions `mouse_button_down_event()`, the
+/// `with_context()` closures must end before calling those functions.
+pub fn touch_event(phase: TouchPhase, id: u64, x: f32, y: f32) {
+    let simulated_mouse = with_context(|context| {
+        context.record(InputCall::Touch { phase, id, x, y });
+        context.touches.insert(id, Touch { id, phase, x, y });
+        context.simulated_mouse_event(phase, id)
+    });
+
+    // call functions that modify context, replaying the touch calls them again
+    without_recording(|| match simulated_mouse {
+        Some(SimulatedMouse::ButtonDown) => mouse_button_down_event(MouseButton::Left, x, y),
+        Some(SimulatedMouse::Motion) => mouse_motion_event(x, y),
+        Some(SimulatedMouse::ButtonUp) => mouse_button_up_event(MouseButton::Left, x, y),
+        None => {}
+    });
+
+    with_context(|context| {
+        context
+            .input_events
+            .push(MiniquadInputEvent::Touch { phase, id, x, y });
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::{fresh_context, serial};
+    use std::panic::{self, AssertUnwindSafe};
+
+    /// Message of the panic raised by `f`
+    fn panic_message(f: impl FnOnce()) -> String {
+        let payload = panic::catch_unwind(AssertUnwindSafe(f)).expect_err("no panic");
+        match payload.downcast::<String>() {
+            Ok(message) => *message,
+            Err(payload) => payload.downcast_ref::<&str>().unwrap().to_string(),
+        }
+    }
+
+    fn on_thread<R: Send + 'static>(name: &str, f: impl FnOnce() -> R + Send + 'static) -> R {
+        thread::Builder::new()
+            .name(name.to_string())
+            .spawn(f)
+            .unwrap()
+            .join()
+            .unwrap()
+    }
+
+    #[test]
+    fn other_threads_are_rejected() {
+        let _context = fresh_context();
+        let main = ThreadInfo::current();
+
+        let errors = on_thread("loader", || {
+            // SAFETY: expected to fail without touching the Context
+            let init = unsafe { init_context() };
+            let shutdown = unsafe { shutdown_context() };
+            [
+                try_with_context(|_| ()).unwrap_err(),
+                try_with_context_ref(|_| ()).unwrap_err(),
+                unsafe { try_get_context() }.unwrap_err(),
+                init.unwrap_err(),
+                shutdown.unwrap_err(),
+            ]
+        });
+        for error in errors {
+            match &error {
+                ContextError::WrongThread {
+                    initialized_on,
+                    accessed_from,
+                } => {
+                    assert_eq!(*initialized_on, main);
+                    assert_eq!(accessed_from.name.as_deref(), Some("loader"));
+                }
+                _ => panic!("{:?}", error),
+            }
+            assert!(error.to_string().contains("'loader'"), "{}", error);
+        }
+        assert_eq!(try_with_context(|_| ()), Ok(()));
+    }
+
+    #[test]
+    fn access_before_init() {
+        let _serial = serial();
+        assert_eq!(
+            unsafe { try_get_context() }.unwrap_err(),
+            ContextError::NotInitialized
+        );
+        assert_eq!(try_with_context(|_| ()), Err(ContextError::NotInitialized));
+        assert_eq!(
+            try_with_context_ref(|_| ()),
+            Err(ContextError::NotInitialized)
+        );
+        assert_eq!(
+            unsafe { shutdown_context() },
+            Err(ContextError::NotInitialized)
+        );
+        let message = panic_message(|| {
+            unsafe { get_context() };
+        });
+        assert_eq!(message, ContextError::NotInitialized.to_string());
+    }
+
+    /// A second `init_context()` keeps the existing `Context` and its handles
+    #[test]
+    fn second_init_is_rejected() {
+        let _context = fresh_context();
+        begin_frame();
+        let generation = context_generation();
+
+        assert_eq!(
+            unsafe { init_context() },
+            Err(ContextError::AlreadyInitialized)
+        );
+        assert_eq!(frame_number(), 1);
+        assert_eq!(context_generation(), generation);
+
+        // nor while the Context is borrowed
+        with_context(|_| {
+            assert_eq!(
+                unsafe { init_context() },
+                Err(ContextError::AlreadyInitialized)
+            );
+            assert_eq!(
+                unsafe { shutdown_context() },
+                Err(ContextError::AlreadyBorrowed)
+            );
+        });
+    }
+
+    /// Without a `Context`, no thread gets as far as `CONTEXT`, and the
+    /// next `init_context()` may happen on any thread
+    #[test]
+    fn no_thread_without_context() {
+        let _serial = serial();
+        let error = on_thread("loader", || try_with_context_ref(|_| ()).unwrap_err());
+        assert_eq!(error, ContextError::NotInitialized);
+
+        on_thread("loader", || unsafe {
+            init_context().unwrap();
+            shutdown_context().unwrap();
+        });
+        assert_eq!(try_with_context(|_| ()), Err(ContextError::NotInitialized));
+    }
+
+    #[test]
+    fn end_frame_reports_dropped_state() {
+        let _context = fresh_context();
+        let subscriber = input::register_input_subscriber();
+        touch_event(TouchPhase::Started, 0, 1., 1.);
+        touch_event(TouchPhase::Started, 1, 2., 2.);
+        touch_event(TouchPhase::Ended, 0, 1., 1.);
+        let report = end_frame();
+        assert_eq!(report.touches_pruned, 1);
+        // pressed and released by the simulated mouse
+        assert_eq!(report.mouse_buttons_cleared, 2);
+        assert_eq!(input::touches().len(), 1);
+        assert_eq!(report.input_events_dropped, 0);
+        assert_eq!(end_frame(), FrameReport::default());
+
+        // only the newest events of a subscriber not drained in time are kept
+        subscriber.drain();
+        let drained = input::register_input_subscriber();
+        for i in 0..MAX_QUEUED_INPUT_EVENTS + 5 {
+            mouse_wheel_event(i as f32, 0.);
+            drained.drain();
+        }
+        assert_eq!(end_frame().input_events_dropped, 5);
+        let events = subscriber.drain();
+        assert_eq!(events.len(), MAX_QUEUED_INPUT_EVENTS);
+        assert_eq!(events[0], MiniquadInputEvent::MouseWheel { x: 5., y: 0. });
+    }
+
+    /// Loaded sounds are assets, not per-frame input
+    #[test]
+    fn end_frame_keeps_loaded_sounds() {
+        let _context = fresh_context();
+        let _mixer = audio::take_audio_mixer().unwrap();
+        let sound = audio::load_sound_from_bytes(&test_utils::wav(1, 1, 8000, 8, &[255])).unwrap();
+        end_frame();
+        assert!(audio::play_sound(sound, Default::default()).is_some());
+    }
+
+    /// Without the `checked` feature the cell is a plain `UnsafeCell`
+    #[cfg(not(feature = "checked"))]
+    #[test]
+    fn unchecked_cell_is_transparent() {
+        assert_eq!(
+            std::mem::size_of::<RacyCell<[u64; 3]>>(),
+            std::mem::size_of::<[u64; 3]>()
+        );
+    }
+
+    /// Borrow tracking of the `checked` feature
+    #[cfg(feature = "checked")]
+    mod checked {
+        use super::*;
+        use crate::test_utils::{context, fresh_context};
+
+        /// An event handler called while a guard of the cell is live, which
+        /// is what `resize_event()` calling `mouse_motion_event()` comes
+        /// down to once the `Context` borrow flag is out of the way
+        #[test]
+        fn event_handler_inside_cell_borrow_panics() {
+            let _context = fresh_context();
+            // SAFETY: the nested handler panics before creating a second borrow
+            let (line, ctx) = (line!(), unsafe { CONTEXT.borrow_mut() });
+            let message = panic_message(|| mouse_motion_event(0., 0.));
+            drop(ctx);
+
+            let expected = format!("RacyCell already mutably borrowed at {}:{}:", file!(), line);
+            assert!(message.starts_with(&expected), "{}", message);
+        }
+
+        /// The `get_context()` pattern of the request, a raw `&mut Context`
+        /// held across `mouse_motion_event()`, is not detected: the pointer
+        /// has no guard. The pointer is not used again, so this is not UB.
+        #[test]
+        fn raw_pointer_is_not_tracked() {
+            let _context = fresh_context();
+            let _ctx = unsafe { get_context() };
+            mouse_motion_event(1., 2.);
+            assert_eq!(context(|c| (c.mouse_x, c.mouse_y)), (1., 2.));
+        }
+
+        #[test]
+        fn panic_names_first_borrow() {
+            let cell = RacyCell::new(0);
+            // SAFETY: the mutable borrow panics before aliasing the shared ones
+            let (line, first) = (line!(), unsafe { cell.borrow() });
+            let _second = unsafe { cell.borrow() };
+            let message = panic_message(|| {
+                let _ = unsafe { cell.borrow_mut() };
+            });
+            assert_eq!(*first, 0);
+
+            // the location of the oldest live borrow, not of the latest one
+            let expected = format!("RacyCell already borrowed at {}:{}:", file!(), line);
+            assert!(message.starts_with(&expected), "{}", message);
+        }
+    }
+}
@@ -0,0 +1,1141 @@
+//! Recording of the calls feeding input into the `Context`, and their replay
+//!
+//! While recording, every event handler ([resize_event()], [touch_event()],
+//! ...), [begin_frame()], [end_frame()] and the setters changing how input is
+//! interpreted ([set_cursor_grab()], [set_touch_mouse_simulation()],
+//! [set_window_constraints()], [set_gamepad_dead_zone()]) is stored with the
+//! number of frames since recording started. Replaying the [Recording] into
+//! a fresh `Context` brings it to the same state, which makes bug reports
+//! reproducible.
+//!
+//! Recordings are saved as text, one call per line after a header naming the
+//! format version:
+//!
+//! ```text
+//! # input recording v1
+//! 0 begin_frame
+//! 1 resize 1920 1080
+//! 1 touch Started 0 10 20.5
+//! 1 key_down A shift+ctrl false
+//! 1 end_frame
+//! ```
+
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::str::{FromStr, SplitWhitespace};
+
+use crate::gamepad::{
+    gamepad_axis_event, gamepad_button_down_event, gamepad_button_up_event,
+    gamepad_connected_event, gamepad_disconnected_event, is_valid_dead_zone, set_gamepad_dead_zone,
+    GamepadAxis, GamepadButton, GamepadId,
+};
+use crate::input::{
+    set_cursor_grab, set_touch_mouse_simulation, KeyCode, KeyMods, MouseButton,
+    TouchMouseSimulation, TouchPhase,
+};
+use crate::window::{set_window_constraints, WindowConstraints};
+use crate::{
+    begin_frame, char_event, dpi_scale_event, end_frame, frame_number, is_valid_dpi_scale,
+    key_down_event, key_up_event, mouse_button_down_event, mouse_button_up_event,
+    mouse_motion_event, mouse_wheel_event, resize_event, touch_event, try_with_context,
+    with_context,
+};
+
+/// First line of saved recordings
+const HEADER: &str = "# input recording v1";
+
+/// A recorded call, named after the function it stands for
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum InputCall {
+    BeginFrame,
+    EndFrame,
+    Resize {
+        width: f32,
+        height: f32,
+    },
+    DpiScale {
+        scale: f32,
+    },
+    MouseMotion {
+        x: f32,
+        y: f32,
+    },
+    MouseWheel {
+        x: f32,
+        y: f32,
+    },
+    MouseButtonDown {
+        button: MouseButton,
+        x: f32,
+        y: f32,
+    },
+    MouseButtonUp {
+        button: MouseButton,
+        x: f32,
+        y: f32,
+    },
+    KeyDown {
+        keycode: KeyCode,
+        modifiers: KeyMods,
+        repeat: bool,
+    },
+    KeyUp {
+        keycode: KeyCode,
+        modifiers: KeyMods,
+    },
+    Char {
+        character: char,
+        modifiers: KeyMods,
+        repeat: bool,
+    },
+    Touch {
+        phase: TouchPhase,
+        id: u64,
+        x: f32,
+        y: f32,
+    },
+    GamepadConnected {
+        id: GamepadId,
+    },
+    GamepadDisconnected {
+        id: GamepadId,
+    },
+    GamepadButtonDown {
+        id: GamepadId,
+        button: GamepadButton,
+    },
+    GamepadButtonUp {
+        id: GamepadId,
+        button: GamepadButton,
+    },
+    GamepadAxis {
+        id: GamepadId,
+        axis: GamepadAxis,
+        value: f32,
+    },
+    SetCursorGrab {
+        grab: bool,
+    },
+    SetTouchMouseSimulation {
+        policy: TouchMouseSimulation,
+    },
+    SetWindowConstraints {
+        constraints: WindowConstraints,
+    },
+    SetGamepadDeadZone {
+        dead_zone: f32,
+    },
+}
+
+impl InputCall {
+    /// Make the call this stands for
+    ///
+    /// # Panics
+    /// - on arguments the function called panics on, which parsed recordings
+    ///   never contain
+    /// - on any [ContextError](crate::ContextError)
+    #[track_caller]
+    pub fn apply(self) {
+        match self {
+            InputCall::BeginFrame => begin_frame(),
+            InputCall::EndFrame => {
+                end_frame();
+            }
+            InputCall::Resize { width, height } => resize_event(width, height),
+            InputCall::DpiScale { scale } => dpi_scale_event(scale),
+            InputCall::MouseMotion { x, y } => mouse_motion_event(x, y),
+            InputCall::MouseWheel { x, y } => mouse_wheel_event(x, y),
+            InputCall::MouseButtonDown { button, x, y } => mouse_button_down_event(button, x, y),
+            InputCall::MouseButtonUp { button, x, y } => mouse_button_up_event(button, x, y),
+            InputCall::KeyDown {
+                keycode,
+                modifiers,
+                repeat,
+            } => key_down_event(keycode, modifiers, repeat),
+            InputCall::KeyUp { keycode, modifiers } => key_up_event(keycode, modifiers),
+            InputCall::Char {
+                character,
+                modifiers,
+                repeat,
+            } => char_event(character, modifiers, repeat),
+            InputCall::Touch { phase, id, x, y } => touch_event(phase, id, x, y),
+            InputCall::GamepadConnected { id } => gamepad_connected_event(id),
+            InputCall::GamepadDisconnected { id } => gamepad_disconnected_event(id),
+            InputCall::GamepadButtonDown { id, button } => gamepad_button_down_event(id, button),
+            InputCall::GamepadButtonUp { id, button } => gamepad_button_up_event(id, button),
+            InputCall::GamepadAxis { id, axis, value } => gamepad_axis_event(id, axis, value),
+            InputCall::SetCursorGrab { grab } => set_cursor_grab(grab),
+            InputCall::SetTouchMouseSimulation { policy } => set_touch_mouse_simulation(policy),
+            InputCall::SetWindowConstraints { constraints } => set_window_constraints(constraints),
+            InputCall::SetGamepadDeadZone { dead_zone } => set_gamepad_dead_zone(dead_zone),
+        }
+    }
+
+    /// Reject the arguments [apply()](InputCall::apply) would panic on, so
+    /// a parsed recording replays without panicking
+    fn check_arguments(&self) -> Result<(), String> {
+        match *self {
+            InputCall::DpiScale { scale } if !is_valid_dpi_scale(scale) => {
+                Err(format!("invalid scale `{}`", scale))
+            }
+            InputCall::SetWindowConstraints { constraints } if !constraints.is_valid() => {
+                Err(format!("invalid window constraints {:?}", constraints))
+            }
+            InputCall::SetGamepadDeadZone { dead_zone } if !is_valid_dead_zone(dead_zone) => {
+                Err(format!("invalid dead zone `{}`", dead_zone))
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+/// A call and the frame it was made on
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RecordedCall {
+    /// [frame_number()] of the call minus the one recording started on
+    pub frame: u64,
+    pub call: InputCall,
+}
+
+/// Calls recorded between [start_recording()] and [stop_recording()].
+///
+/// [Display](fmt::Display) and [FromStr] convert from and to the text format
+/// described in the [module documentation](self).
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Recording {
+    calls: Vec<RecordedCall>,
+}
+
+impl Recording {
+    pub fn calls(&self) -> &[RecordedCall] {
+        &self.calls
+    }
+
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<(), RecordingError> {
+        fs::write(path, self.to_string())?;
+        Ok(())
+    }
+
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, RecordingError> {
+        fs::read_to_string(path)?.parse()
+    }
+
+    /// Make all recorded calls again. The `Context` must be in the state it
+    /// was when recording started, usually freshly initialized or reset.
+    /// Frames are counted from the frame the replay starts on.
+    ///
+    /// Returns [RecordingError::FrameMismatch], without making the call, if
+    /// the frames the `Context` went through since then differ from the
+    /// recorded ones.
+    ///
+    /// # Panics
+    /// On any [ContextError](crate::ContextError)
+    #[track_caller]
+    pub fn replay(&self) -> Result<(), RecordingError> {
+        let start_frame = frame_number();
+        for recorded in &self.calls {
+            let current = frame_number() - start_frame;
+            if current != recorded.frame {
+                return Err(RecordingError::FrameMismatch {
+                    recorded: recorded.frame,
+                    current,
+                });
+            }
+            recorded.call.apply();
+        }
+        Ok(())
+    }
+}
+
+/// Error of saving, loading or replaying a [Recording]
+#[derive(Debug)]
+pub enum RecordingError {
+    Io(io::Error),
+    /// A line of a saved recording could not be parsed, `line` starts at 1
+    Parse {
+        line: usize,
+        reason: String,
+    },
+    /// A call was recorded another number of frames after the start than
+    /// the `Context` went through when replaying it
+    FrameMismatch {
+        recorded: u64,
+        current: u64,
+    },
+}
+
+impl fmt::Display for RecordingError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            RecordingError::Io(err) => write!(f, "cannot access recording: {}", err),
+            RecordingError::Parse { line, reason } => {
+                write!(f, "invalid recording at line {}: {}", line, reason)
+            }
+            RecordingError::FrameMismatch { recorded, current } => write!(
+                f,
+                "call recorded {} frames after the start replayed {} frames after it",
+                recorded, current
+            ),
+        }
+    }
+}
+
+impl std::error::Error for RecordingError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            RecordingError::Io(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for RecordingError {
+    fn from(err: io::Error) -> Self {
+        RecordingError::Io(err)
+    }
+}
+
+/// Start recording calls, dropping any recording in progress.
+///
+/// # Panics
+/// On any [ContextError](crate::ContextError)
+#[track_caller]
+pub fn start_recording() {
+    with_context(|context| {
+        context.recording = Some(ActiveRecording {
+            start_frame: context.frame,
+            recording: Recording::default(),
+        })
+    });
+}
+
+/// Stop recording and return the recorded calls, `None` if
+/// [start_recording()] was not called.
+///
+/// # Panics
+/// On any [ContextError](crate::ContextError)
+#[track_caller]
+pub fn stop_recording() -> Option<Recording> {
+    with_context(|context| context.recording.take().map(|active| active.recording))
+}
+
+/// The [Recording] between [start_recording()] and [stop_recording()]
+#[derive(Debug)]
+pub(crate) struct ActiveRecording {
+    start_frame: u64,
+    recording: Recording,
+}
+
+impl ActiveRecording {
+    /// `frame` is the [frame_number()] of the call
+    pub(crate) fn push(&mut self, frame: u64, call: InputCall) {
+        self.recording.calls.push(RecordedCall {
+            frame: frame - self.start_frame,
+            call,
+        });
+    }
+}
+
+/// Run `f` without recording, for handlers calling other handlers: replaying
+/// the outer call makes the inner calls again.
+pub(crate) fn without_recording<R>(f: impl FnOnce() -> R) -> R {
+    let _paused = PausedRecording(with_context(|context| context.recording.take()));
+    f()
+}
+
+/// Puts the recording back when dropped, also when unwinding out of
+/// [without_recording()]
+struct PausedRecording(Option<ActiveRecording>);
+
+impl Drop for PausedRecording {
+    fn drop(&mut self) {
+        if let Some(recording) = self.0.take() {
+            // panicking while unwinding would abort, the recording is lost
+            // instead if the `Context` is gone or borrowed
+            let _ = try_with_context(|context| context.recording = Some(recording));
+        }
+    }
+}
+
+/// Types written by their variant name
+trait Named: Copy {
+    fn name(self) -> &'static str;
+    fn from_name(name: &str) -> Option<Self>;
+}
+
+/// The match in `name()` is exhaustive, so a variant missing from the list
+/// fails to compile instead of failing to parse
+macro_rules! named {
+    ($ty:ident { $($variant:ident),* $(,)? }) => {
+        impl Named for $ty {
+            fn name(self) -> &'static str {
+                match self {
+                    $($ty::$variant => stringify!($variant),)*
+                }
+            }
+
+            fn from_name(name: &str) -> Option<Self> {
+                match name {
+                    $(stringify!($variant) => Some($ty::$variant),)*
+                    _ => None,
+                }
+            }
+        }
+
+        #[cfg(test)]
+        impl $ty {
+            const ALL: &'static [$ty] = &[$($ty::$variant),*];
+        }
+    };
+}
+
+named!(MouseButton {
+    Right,
+    Left,
+    Middle,
+    Unknown
+});
+named!(TouchPhase {
+    Started,
+    Moved,
+    Ended,
+    Cancelled
+});
+named!(TouchMouseSimulation {
+    Off,
+    PrimaryTouchOnly,
+    AllTouches
+});
+named!(GamepadButton {
+    South,
+    East,
+    West,
+    North,
+    LeftShoulder,
+    RightShoulder,
+    Select,
+    Start,
+    Guide,
+    LeftStick,
+    RightStick,
+    DPadUp,
+    DPadDown,
+    DPadLeft,
+    DPadRight,
+});
+named!(GamepadAxis {
+    LeftStickX,
+    LeftStickY,
+    RightStickX,
+    RightStickY,
+    LeftTrigger,
+    RightTrigger
+});
+named!(KeyCode {
+    Space,
+    Apostrophe,
+    Comma,
+    Minus,
+    Period,
+    Slash,
+    Key0,
+    Key1,
+    Key2,
+    Key3,
+    Key4,
+    Key5,
+    Key6,
+    Key7,
+    Key8,
+    Key9,
+    Semicolon,
+    Equal,
+    A,
+    B,
+    C,
+    D,
+    E,
+    F,
+    G,
+    H,
+    I,
+    J,
+    K,
+    L,
+    M,
+    N,
+    O,
+    P,
+    Q,
+    R,
+    S,
+    T,
+    U,
+    V,
+    W,
+    X,
+    Y,
+    Z,
+    LeftBracket,
+    Backslash,
+    RightBracket,
+    GraveAccent,
+    Escape,
+    Enter,
+    Tab,
+    Backspace,
+    Insert,
+    Delete,
+    Right,
+    Left,
+    Down,
+    Up,
+    PageUp,
+    PageDown,
+    Home,
+    End,
+    CapsLock,
+    F1,
+    F2,
+    F3,
+    F4,
+    F5,
+    F6,
+    F7,
+    F8,
+    F9,
+    F10,
+    F11,
+    F12,
+    LeftShift,
+    LeftControl,
+    LeftAlt,
+    LeftSuper,
+    RightShift,
+    RightControl,
+    RightAlt,
+    RightSuper,
+    Unknown,
+});
+
+/// Written as `-` or the held modifiers joined by `+`, like `shift+ctrl`
+struct FormatMods(KeyMods);
+
+impl fmt::Display for FormatMods {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let KeyMods {
+            shift,
+            ctrl,
+            alt,
+            logo,
+        } = self.0;
+        let held: Vec<_> = [
+            (shift, "shift"),
+            (ctrl, "ctrl"),
+            (alt, "alt"),
+            (logo, "logo"),
+        ]
+        .iter()
+        .filter(|(held, _)| *held)
+        .map(|(_, name)| *name)
+        .collect();
+        if held.is_empty() {
+            write!(f, "-")
+        } else {
+            write!(f, "{}", held.join("+"))
+        }
+    }
+}
+
+/// Written as `-` or `<width>x<height>`
+struct FormatSize(Option<(f32, f32)>);
+
+impl fmt::Display for FormatSize {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.0 {
+            Some((width, height)) => write!(f, "{}x{}", width, height),
+            None => write!(f, "-"),
+        }
+    }
+}
+
+impl fmt::Display for InputCall {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            InputCall::BeginFrame => write!(f, "begin_frame"),
+            InputCall::EndFrame => write!(f, "end_frame"),
+            InputCall::Resize { width, height } => write!(f, "resize {} {}", width, height),
+            InputCall::DpiScale { scale } => write!(f, "dpi_scale {}", scale),
+            InputCall::MouseMotion { x, y } => write!(f, "mouse_motion {} {}", x, y),
+            InputCall::MouseWheel { x, y } => write!(f, "mouse_wheel {} {}", x, y),
+            InputCall::MouseButtonDown { button, x, y } => {
+                write!(f, "mouse_button_down {} {} {}", button.name(), x, y)
+            }
+            InputCall::MouseButtonUp { button, x, y } => {
+                write!(f, "mouse_button_up {} {} {}", button.name(), x, y)
+            }
+            InputCall::KeyDown {
+                keycode,
+                modifiers,
+                repeat,
+            } => write!(
+                f,
+                "key_down {} {} {}",
+                keycode.name(),
+                FormatMods(modifiers),
+                repeat
+            ),
+            InputCall::KeyUp { keycode, modifiers } => {
+                write!(f, "key_up {} {}", keycode.name(), FormatMods(modifiers))
+            }
+            // as a code point, characters may be whitespace
+            InputCall::Char {
+                character,
+                modifiers,
+                repeat,
+            } => write!(
+                f,
+                "char {} {} {}",
+                character as u32,
+                FormatMods(modifiers),
+                repeat
+            ),
+            InputCall::Touch { phase, id, x, y } => {
+                write!(f, "touch {} {} {} {}", phase.name(), id, x, y)
+            }
+            InputCall::GamepadConnected { id } => write!(f, "gamepad_connected {}", id.0),
+            InputCall::GamepadDisconnected { id } => write!(f, "gamepad_disconnected {}", id.0),
+            InputCall::GamepadButtonDown { id, button } => {
+                write!(f, "gamepad_button_down {} {}", id.0, button.name())
+            }
+            InputCall::GamepadButtonUp { id, button } => {
+                write!(f, "gamepad_button_up {} {}", id.0, button.name())
+            }
+            InputCall::GamepadAxis { id, axis, value } => {
+                write!(f, "gamepad_axis {} {} {}", id.0, axis.name(), value)
+            }
+            InputCall::SetCursorGrab { grab } => write!(f, "set_cursor_grab {}", grab),
+            InputCall::SetTouchMouseSimulation { policy } => {
+                write!(f, "set_touch_mouse_simulation {}", policy.name())
+            }
+            InputCall::SetWindowConstraints { constraints } => {
+                write!(
+                    f,
+                    "set_window_constraints {} {} ",
+                    FormatSize(constraints.min_size),
+                    FormatSize(constraints.max_size)
+                )?;
+                match constraints.aspect_ratio {
+                    Some(ratio) => write!(f, "{}", ratio),
+                    None => write!(f, "-"),
+                }
+            }
+            InputCall::SetGamepadDeadZone { dead_zone } => {
+                write!(f, "set_gamepad_dead_zone {}", dead_zone)
+            }
+        }
+    }
+}
+
+impl fmt::Display for Recording {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "{}", HEADER)?;
+        for recorded in &self.calls {
+            writeln!(f, "{} {}", recorded.frame, recorded.call)?;
+        }
+        Ok(())
+    }
+}
+
+/// The whitespace separated fields of a line, errors name the missing or
+/// invalid field
+struct Fields<'a>(SplitWhitespace<'a>);
+
+impl<'a> Fields<'a> {
+    fn next(&mut self, what: &str) -> Result<&'a str, String> {
+        self.0.next().ok_or_else(|| format!("missing {}", what))
+    }
+
+    fn parse<T: FromStr>(&mut self, what: &str) -> Result<T, String> {
+        let field = self.next(what)?;
+        field
+            .parse()
+            .map_err(|_| format!("invalid {} `{}`", what, field))
+    }
+
+    fn named<T: Named>(&mut self, what: &str) -> Result<T, String> {
+        let field = self.next(what)?;
+        T::from_name(field).ok_or_else(|| format!("invalid {} `{}`", what, field))
+    }
+
+    fn gamepad(&mut self) -> Result<GamepadId, String> {
+        self.parse("gamepad id").map(GamepadId)
+    }
+
+    fn mods(&mut self) -> Result<KeyMods, String> {
+        let field = self.next("modifiers")?;
+        let mut mods = KeyMods::default();
+        if field == "-" {
+            return Ok(mods);
+        }
+        for name in field.split('+') {
+            match name {
+                "shift" => mods.shift = true,
+                "ctrl" => mods.ctrl = true,
+                "alt" => mods.alt = true,
+                "logo" => mods.logo = true,
+                _ => return Err(format!("invalid modifier `{}`", name)),
+            }
+        }
+        Ok(mods)
+    }
+
+    fn character(&mut self) -> Result<char, String> {
+        let code: u32 = self.parse("character")?;
+        char::from_u32(code).ok_or_else(|| format!("invalid character `{}`", code))
+    }
+
+    fn size(&mut self, what: &str) -> Result<Option<(f32, f32)>, String> {
+        let field = self.next(what)?;
+        if field == "-" {
+            return Ok(None);
+        }
+        field
+            .split_once('x')
+            .and_then(|(width, height)| Some((width.parse().ok()?, height.parse().ok()?)))
+            .map(Some)
+            .ok_or_else(|| format!("invalid {} `{}`", what, field))
+    }
+
+    fn optional<T: FromStr>(&mut self, what: &str) -> Result<Option<T>, String> {
+        let field = self.next(what)?;
+        if field == "-" {
+            return Ok(None);
+        }
+        field
+            .parse()
+            .map(Some)
+            .map_err(|_| format!("invalid {} `{}`", what, field))
+    }
+
+    fn end(mut self) -> Result<(), String> {
+        match self.0.next() {
+            Some(field) => Err(format!("unexpected `{}`", field)),
+            None => Ok(()),
+        }
+    }
+}
+
+fn parse_line(line: &str) -> Result<RecordedCall, String> {
+    let mut fields = Fields(line.split_whitespace());
+    let frame = fields.parse("frame number")?;
+    let call = match fields.next("call")? {
+        "begin_frame" => InputCall::BeginFrame,
+        "end_frame" => InputCall::EndFrame,
+        "resize" => InputCall::Resize {
+            width: fields.parse("width")?,
+            height: fields.parse("height")?,
+        },
+        "dpi_scale" => InputCall::DpiScale {
+            scale: fields.parse("scale")?,
+        },
+        "mouse_motion" => InputCall::MouseMotion {
+            x: fields.parse("x")?,
+            y: fields.parse("y")?,
+        },
+        "mouse_wheel" => InputCall::MouseWheel {
+            x: fields.parse("x")?,
+            y: fields.parse("y")?,
+        },
+        "mouse_button_down" => InputCall::MouseButtonDown {
+            button: fields.named("mouse button")?,
+            x: fields.parse("x")?,
+            y: fields.parse("y")?,
+        },
+        "mouse_button_up" => InputCall::MouseButtonUp {
+            button: fields.named("mouse button")?,
+            x: fields.parse("x")?,
+            y: fields.parse("y")?,
+        },
+        "key_down" => InputCall::KeyDown {
+            keycode: fields.named("key code")?,
+            modifiers: fields.mods()?,
+            repeat: fields.parse("repeat")?,
+        },
+        "key_up" => InputCall::KeyUp {
+            keycode: fields.named("key code")?,
+            modifiers: fields.mods()?,
+        },
+        "char" => InputCall::Char {
+            character: fields.character()?,
+            modifiers: fields.mods()?,
+            repeat: fields.parse("repeat")?,
+        },
+        "touch" => InputCall::Touch {
+            phase: fields.named("touch phase")?,
+            id: fields.parse("touch id")?,
+            x: fields.parse("x")?,
+            y: fields.parse("y")?,
+        },
+        "gamepad_connected" => InputCall::GamepadConnected {
+            id: fields.gamepad()?,
+        },
+        "gamepad_disconnected" => InputCall::GamepadDisconnected {
+            id: fields.gamepad()?,
+        },
+        "gamepad_button_down" => InputCall::GamepadButtonDown {
+            id: fields.gamepad()?,
+            button: fields.named("gamepad button")?,
+        },
+        "gamepad_button_up" => InputCall::GamepadButtonUp {
+            id: fields.gamepad()?,
+            button: fields.named("gamepad button")?,
+        },
+        "gamepad_axis" => InputCall::GamepadAxis {
+            id: fields.gamepad()?,
+            axis: fields.named("gamepad axis")?,
+            value: fields.parse("axis value")?,
+        },
+        "set_cursor_grab" => InputCall::SetCursorGrab {
+            grab: fields.parse("grab")?,
+        },
+        "set_touch_mouse_simulation" => InputCall::SetTouchMouseSimulation {
+            policy: fields.named("touch mouse simulation")?,
+        },
+        "set_window_constraints" => InputCall::SetWindowConstraints {
+            constraints: WindowConstraints {
+                min_size: fields.size("min size")?,
+                max_size: fields.size("max size")?,
+                aspect_ratio: fields.optional("aspect ratio")?,
+            },
+        },
+        "set_gamepad_dead_zone" => InputCall::SetGamepadDeadZone {
+            dead_zone: fields.parse("dead zone")?,
+        },
+        call => return Err(format!("unknown call `{}`", call)),
+    };
+    fields.end()?;
+    call.check_arguments()?;
+    Ok(RecordedCall { frame, call })
+}
+
+impl FromStr for Recording {
+    type Err = RecordingError;
+
+    fn from_str(text: &str) -> Result<Self, Self::Err> {
+        let mut lines = text.lines().enumerate();
+        let first = lines.next().map_or("", |(_, line)| line.trim_end());
+        if first != HEADER {
+            return Err(RecordingError::Parse {
+                line: 1,
+                reason: format!("expected `{}`, found `{}`", HEADER, first),
+            });
+        }
+        let mut calls = vec![];
+        for (index, line) in lines {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let recorded = parse_line(line).map_err(|reason| RecordingError::Parse {
+                line: index + 1,
+                reason,
+            })?;
+            calls.push(recorded);
+        }
+        Ok(Recording { calls })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::gamepad::Gamepads;
+    use crate::input::{get_char_pressed, mouse_position, InputEvents, Touch};
+    use crate::test_utils::{check_golden, context, fixture_path, fresh_context};
+    use crate::{init_context, reset_context, shutdown_context, Context};
+    use std::collections::{BTreeMap, BTreeSet, VecDeque};
+
+    const SESSION_REPLAY: &str = include_str!("../tests/fixtures/session.replay");
+
+    /// The state replaying reproduces, the rest of the `Context` is checked
+    /// by the tests that need it
+    fn input_state(c: &Context) -> String {
+        // only read by the Debug output
+        #[derive(Debug)]
+        #[allow(dead_code)]
+        struct InputState<'a> {
+            frame: u64,
+            screen_size: (f32, f32),
+            physical_size: (f32, f32),
+            dpi_scale: f32,
+            window_constraints: WindowConstraints,
+            mouse: (f32, f32),
+            reported_mouse: Option<(f32, f32)>,
+            mouse_delta: (f32, f32),
+            mouse_wheel: (f32, f32),
+            cursor_grabbed: bool,
+            mouse_down: &'a BTreeSet<MouseButton>,
+            mouse_pressed: &'a BTreeSet<MouseButton>,
+            mouse_released: &'a BTreeSet<MouseButton>,
+            keys_down: &'a BTreeSet<KeyCode>,
+            keys_pressed: &'a BTreeSet<KeyCode>,
+            keys_repeated: &'a BTreeSet<KeyCode>,
+            keys_released: &'a BTreeSet<KeyCode>,
+            key_mods: KeyMods,
+            chars_pressed: &'a VecDeque<char>,
+            touches: &'a BTreeMap<u64, Touch>,
+            touch_mouse_simulation: TouchMouseSimulation,
+            simulated_touches: &'a BTreeSet<u64>,
+            mouse_touch: Option<u64>,
+            gamepads: &'a Gamepads,
+            input_events: &'a InputEvents,
+        }
+        format!(
+            "{:#?}",
+            InputState {
+                frame: c.frame,
+                screen_size: (c.screen_width, c.screen_height),
+                physical_size: (c.physical_width, c.physical_height),
+                dpi_scale: c.dpi_scale,
+                window_constraints: c.window_constraints,
+                mouse: (c.mouse_x, c.mouse_y),
+                reported_mouse: c.reported_mouse,
+                mouse_delta: (c.mouse_delta_x, c.mouse_delta_y),
+                mouse_wheel: (c.mouse_wheel_x, c.mouse_wheel_y),
+                cursor_grabbed: c.cursor_grabbed,
+                mouse_down: &c.mouse_down,
+                mouse_pressed: &c.mouse_pressed,
+                mouse_released: &c.mouse_released,
+                keys_down: &c.keys_down,
+                keys_pressed: &c.keys_pressed,
+                keys_repeated: &c.keys_repeated,
+                keys_released: &c.keys_released,
+                key_mods: c.key_mods,
+                chars_pressed: &c.chars_pressed,
+                touches: &c.touches,
+                touch_mouse_simulation: c.touch_mouse_simulation,
+                simulated_touches: &c.simulated_touches,
+                mouse_touch: c.mouse_touch,
+                gamepads: &c.gamepads,
+                input_events: &c.input_events,
+            }
+        )
+    }
+
+    /// A little of every kind of input
+    fn play_session() {
+        begin_frame();
+        resize_event(1920., 1080.);
+        dpi_scale_event(1.5);
+        set_window_constraints(WindowConstraints {
+            min_size: Some((320., 240.)),
+            max_size: None,
+            aspect_ratio: Some(16. / 9.),
+        });
+        mouse_motion_event(12.5, 40.);
+        key_down_event(
+            KeyCode::A,
+            KeyMods {
+                shift: true,
+                ..KeyMods::default()
+            },
+            false,
+        );
+        char_event(' ', KeyMods::default(), false);
+        touch_event(TouchPhase::Started, 7, 100., 200.);
+        end_frame();
+
+        begin_frame();
+        touch_event(TouchPhase::Moved, 7, 110., 190.);
+        set_touch_mouse_simulation(TouchMouseSimulation::AllTouches);
+        key_up_event(KeyCode::A, KeyMods::default());
+        gamepad_connected_event(GamepadId(1));
+        gamepad_button_down_event(GamepadId(1), GamepadButton::Start);
+        gamepad_axis_event(GamepadId(1), GamepadAxis::RightTrigger, 0.75);
+        set_cursor_grab(true);
+        mouse_wheel_event(0., -3.);
+        mouse_button_down_event(MouseButton::Right, 5., 5.);
+        end_frame();
+    }
+
+    #[test]
+    fn replay_matches_golden_state() {
+        let _context = fresh_context();
+        start_recording();
+        play_session();
+        let recording = stop_recording().unwrap();
+        let live_state = context(input_state);
+        let text = recording.to_string();
+        check_golden("session.replay", SESSION_REPLAY, &text);
+
+        let loaded: Recording = SESSION_REPLAY.parse().unwrap();
+        assert_eq!(loaded, recording);
+
+        unsafe {
+            shutdown_context().unwrap();
+            init_context().unwrap();
+        }
+        loaded.replay().unwrap();
+        let replayed_state = context(input_state);
+        assert_eq!(replayed_state, live_state);
+        // replaying leaves graphics and audio alone
+        assert_eq!(
+            context(|c| (c.quad_context, c.gl, c.gl_token_taken)),
+            (0, 0, false)
+        );
+        assert!(context(|c| c.recording.is_none()));
+        check_golden(
+            "session.context",
+            include_str!("../tests/fixtures/session.context"),
+            &replayed_state,
+        );
+    }
+
+    #[test]
+    #[cfg_attr(miri, ignore = "reads the fixture from disk")]
+    fn load_golden_recording() {
+        let loaded = Recording::load(fixture_path("session.replay")).unwrap();
+        assert_eq!(loaded, SESSION_REPLAY.parse().unwrap());
+    }
+
+    #[test]
+    fn inner_calls_are_not_recorded() {
+        let _context = fresh_context();
+        start_recording();
+        touch_event(TouchPhase::Started, 0, 1., 2.);
+        // releases the simulated button
+        set_touch_mouse_simulation(TouchMouseSimulation::Off);
+        // queries are not recorded
+        assert_eq!(mouse_position(), (1., 2.));
+        assert_eq!(get_char_pressed(), None);
+
+        let calls: Vec<_> = stop_recording()
+            .unwrap()
+            .calls()
+            .iter()
+            .map(|c| c.call)
+            .collect();
+        assert_eq!(
+            calls,
+            [
+                InputCall::Touch {
+                    phase: TouchPhase::Started,
+                    id: 0,
+                    x: 1.,
+                    y: 2.
+                },
+                InputCall::SetTouchMouseSimulation {
+                    policy: TouchMouseSimulation::Off
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn recording_survives_panicking_inner_call() {
+        let _context = fresh_context();
+        start_recording();
+        let result = std::panic::catch_unwind(|| without_recording(|| panic!("inner call")));
+        assert!(result.is_err());
+        begin_frame();
+        assert_eq!(stop_recording().unwrap().calls().len(), 1);
+    }
+
+    /// The usual bug report: recording starts after the game has been
+    /// running for a while
+    #[test]
+    fn recording_started_mid_session() {
+        let _context = fresh_context();
+        for _ in 0..2 {
+            begin_frame();
+            end_frame();
+        }
+        start_recording();
+        begin_frame();
+        mouse_motion_event(3., 4.);
+        end_frame();
+        let recording = stop_recording().unwrap();
+        assert_eq!(recording.calls()[0].frame, 0);
+        assert_eq!(recording.calls()[1].frame, 1);
+
+        unsafe { reset_context() }.unwrap();
+        recording.replay().unwrap();
+        assert_eq!(mouse_position(), (3., 4.));
+        assert_eq!(frame_number(), 1);
+    }
+
+    #[test]
+    fn replay_with_missing_frame() {
+        let _context = fresh_context();
+        let recording: Recording = format!("{}\n0 begin_frame\n2 end_frame", HEADER)
+            .parse()
+            .unwrap();
+        begin_frame();
+        assert!(matches!(
+            recording.replay(),
+            Err(RecordingError::FrameMismatch {
+                recorded: 2,
+                current: 1
+            })
+        ));
+    }
+
+    fn check_names<T: Named + fmt::Debug + PartialEq>(all: &[T]) {
+        for &value in all {
+            // recordings written before `name()` used the Debug output
+            assert_eq!(value.name(), format!("{:?}", value));
+            assert_eq!(T::from_name(value.name()), Some(value));
+        }
+    }
+
+    #[test]
+    fn every_variant_round_trips() {
+        check_names(MouseButton::ALL);
+        check_names(TouchPhase::ALL);
+        check_names(TouchMouseSimulation::ALL);
+        check_names(GamepadButton::ALL);
+        check_names(GamepadAxis::ALL);
+        check_names(KeyCode::ALL);
+    }
+
+    #[test]
+    fn parse_errors() {
+        let parse = |calls: &str| format!("{}\n{}", HEADER, calls).parse::<Recording>();
+        let error = parse("# comment\n0 begin_frame\n\n3 key_down Kp0 - false").unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "invalid recording at line 5: invalid key code `Kp0`"
+        );
+        assert!(parse("0 resize 10").is_err());
+        assert!(parse("0 end_frame now").is_err());
+
+        // values the functions would panic on
+        assert_eq!(
+            parse("0 set_gamepad_dead_zone 2").unwrap_err().to_string(),
+            "invalid recording at line 2: invalid dead zone `2`"
+        );
+        assert!(parse("0 dpi_scale 0").is_err());
+        assert!(parse("0 dpi_scale NaN").is_err());
+        assert!(parse("0 set_window_constraints -1x0 - -").is_err());
+        assert!(parse("0 set_window_constraints - - inf").is_err());
+    }
+
+    #[test]
+    fn header_is_required() {
+        assert_eq!(
+            "0 begin_frame"
+                .parse::<Recording>()
+                .unwrap_err()
+                .to_string(),
+            "invalid recording at line 1: expected `# input recording v1`, found `0 begin_frame`"
+        );
+        assert!("# input recording v2\n0 begin_frame"
+            .parse::<Recording>()
+            .is_err());
+        assert!("".parse::<Recording>().is_err());
+        assert_eq!(HEADER.parse::<Recording>().unwrap(), Recording::default());
+    }
+}
@@ -0,0 +1,92 @@
+//! Helpers for tests using the global `Context`
+
+use crate::{init_context, shutdown_context, with_context_ref, Context};
+use std::env;
+use std::fs;
+use std::path::PathBuf;
+use std::sync::{Mutex, MutexGuard, PoisonError};
+
+/// Serializes tests, since they all share the global `Context`
+static SERIAL: Mutex<()> = Mutex::new(());
+
+/// Holds a fresh `Context` bound to the test thread, shut down on drop
+pub(crate) struct FreshContext {
+    _serial: MutexGuard<'static, ()>,
+}
+
+impl Drop for FreshContext {
+    fn drop(&mut self) {
+        // SAFETY: tests do not keep references past the end of the test
+        let _ = unsafe { shutdown_context() };
+    }
+}
+
+/// Excludes the other tests without creating a `Context`
+pub(crate) fn serial() -> MutexGuard<'static, ()> {
+    SERIAL.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+pub(crate) fn fresh_context() -> FreshContext {
+    let serial = serial();
+    // SAFETY: the previous test shut its context down
+    unsafe { init_context() }.unwrap();
+    FreshContext { _serial: serial }
+}
+
+/// Inspect the `Context` through the shared borrow path
+#[track_caller]
+pub(crate) fn context<R>(f: impl FnOnce(&Context) -> R) -> R {
+    with_context_ref(f)
+}
+
+/// A RIFF/WAVE file with a 16 byte fmt chunk followed by `data`
+pub(crate) fn wav(
+    format: u16,
+    channels: u16,
+    sample_rate: u32,
+    bits_per_sample: u16,
+    data: &[u8],
+) -> Vec<u8> {
+    let block_align = channels * bits_per_sample / 8;
+    let mut wav = vec![];
+    wav.extend_from_slice(b"RIFF");
+    wav.extend_from_slice(&(36 + data.len() as u32).to_le_bytes());
+    wav.extend_from_slice(b"WAVEfmt ");
+    wav.extend_from_slice(&16u32.to_le_bytes());
+    wav.extend_from_slice(&format.to_le_bytes());
+    wav.extend_from_slice(&channels.to_le_bytes());
+    wav.extend_from_slice(&sample_rate.to_le_bytes());
+    wav.extend_from_slice(&(sample_rate * block_align as u32).to_le_bytes());
+    wav.extend_from_slice(&block_align.to_le_bytes());
+    wav.extend_from_slice(&bits_per_sample.to_le_bytes());
+    wav.extend_from_slice(b"data");
+    wav.extend_from_slice(&(data.len() as u32).to_le_bytes());
+    wav.extend_from_slice(data);
+    wav
+}
+
+/// Golden files are rewritten instead of compared when this environment
+/// variable is set
+const UPDATE_GOLDEN: &str = "UPDATE_GOLDEN";
+
+pub(crate) fn fixture_path(name: &str) -> PathBuf {
+    [env!("CARGO_MANIFEST_DIR"), "tests", "fixtures", name]
+        .iter()
+        .collect()
+}
+
+/// Compare `actual` to the fixture `name`, whose content is passed as
+/// `expected` with `include_str!()` so the comparison does not need file
+/// system access under Miri
+#[track_caller]
+pub(crate) fn check_golden(name: &str, expected: &str, actual: &str) {
+    if env::var_os(UPDATE_GOLDEN).is_some() {
+        fs::write(fixture_path(name), actual).unwrap();
+        return;
+    }
+    assert_eq!(
+        actual, expected,
+        "{} differs, set {} to update it",
+        name, UPDATE_GOLDEN
+    );
+}
@@ -0,0 +1,321 @@
+//! Models of the `Context` access patterns documented in the library,
+//! classified as sound or unsound.
+//!
+//! Sound patterns run by default and must pass under Miri with both aliasing
+//! models. Unsound patterns are `#[ignore]`d and must be reported as UB by
+//! the models named in their doc comment:
+//!
+//! ```text
+//! cargo +nightly miri test
+//! MIRIFLAGS=-Zmiri-tree-borrows cargo +nightly miri test
+//! cargo +nightly miri test -- --ignored unsound_nested_get_context_in_resize_event
+//! ```
+//!
+//! Miri aborts on the first UB, so run unsound patterns one at a time.
+//!
+//! The `checked` feature catches none of the unsound patterns: they alias
+//! through raw pointers, which it does not track (see [RacyCell]).
+
+use super::*;
+use crate::audio::{
+    is_voice_playing, load_sound_from_bytes, play_sound, stop_sound, take_audio_mixer,
+    PlaySoundParams, SoundData,
+};
+use crate::input::{mouse_position, MiniquadInputEvent, Touch, TouchPhase};
+use crate::test_utils::{context, fresh_context, serial, wav};
+use crate::window::{
+    get_internal_gl, screen_width, take_gl_token, with_internal_gl, InternalGlContext,
+};
+
+/// `resize_event()` followed by `mouse_motion_event()`: each borrow ends
+/// before the next one starts
+#[test]
+fn sound_resize_then_mouse_motion() {
+    let _context = fresh_context();
+    resize_event(1920., 1080.);
+    mouse_motion_event(42., 84.);
+
+    context(|context| {
+        assert_eq!(
+            (context.screen_width, context.screen_height),
+            (1920., 1080.)
+        );
+        assert_eq!((context.mouse_x, context.mouse_y), (42., 84.));
+    });
+}
+
+/// The nested call from `resize_event()` is rejected by `with_context()`
+#[test]
+fn sound_nested_with_context_is_rejected() {
+    let _context = fresh_context();
+    with_context(|ctx| {
+        assert_eq!(try_with_context(|_| ()), Err(ContextError::AlreadyBorrowed));
+        ctx.screen_height = 1080.;
+    });
+    assert_eq!(context(|c| c.screen_height), 1080.);
+}
+
+#[test]
+#[should_panic(expected = "Context is already borrowed")]
+fn sound_nested_mouse_motion_event_panics() {
+    let _context = fresh_context();
+    with_context(|_ctx| mouse_motion_event(0., 0.));
+}
+
+/// Read-only queries share the `Context`, so they nest, while handlers
+/// needing `&mut Context` are rejected
+#[test]
+fn sound_nested_shared_queries() {
+    let _context = fresh_context();
+    resize_event(1920., 1080.);
+    mouse_motion_event(42., 84.);
+
+    with_context_ref(|ctx| {
+        assert_eq!(screen_width(), ctx.screen_width);
+        assert_eq!(mouse_position(), (ctx.mouse_x, ctx.mouse_y));
+        assert_eq!(try_with_context(|_| ()), Err(ContextError::AlreadyBorrowed));
+    });
+}
+
+/// `resize_event()` with the commented out `mouse_motion_event()` call
+/// restored on a raw `&mut Context`.
+///
+/// UB under Stacked Borrows only: the `&mut Context` created by
+/// `mouse_motion_event()` invalidates `ctx`. Tree Borrows accepts it
+/// because the two writes are to disjoint fields.
+#[test]
+#[ignore = "UB (Stacked Borrows): mouse_motion_event() invalidates ctx"]
+fn unsound_nested_get_context_in_resize_event() {
+    let _context = fresh_context();
+    unsafe {
+        let ctx = &mut *get_context();
+        mouse_motion_event(0., 0.);
+        ctx.screen_height = 1080.;
+    }
+}
+
+/// `touch_event()` drops its borrow around `mouse_motion_event()`
+#[test]
+fn sound_touch_event_interleaving() {
+    let _context = fresh_context();
+    touch_event(TouchPhase::Started, 0, 1., 2.);
+    touch_event(TouchPhase::Ended, 0, 3., 4.);
+
+    context(|context| {
+        assert_eq!(context.touches.len(), 1);
+        assert_eq!((context.mouse_x, context.mouse_y), (3., 4.));
+    });
+}
+
+/// `touch_event()` keeping one `&mut Context` across the simulated mouse
+/// event.
+///
+/// UB under Stacked Borrows only: the `&mut Context` created by
+/// `mouse_motion_event()` invalidates `context`. Tree Borrows accepts it
+/// because the writes are to disjoint fields.
+#[test]
+#[ignore = "UB (Stacked Borrows): mouse_motion_event() invalidates context"]
+fn unsound_touch_event_holding_context() {
+    let _context = fresh_context();
+    unsafe {
+        let context = &mut *get_context();
+        let touch = Touch {
+            id: 0,
+            phase: TouchPhase::Started,
+            x: 1.,
+            y: 2.,
+        };
+        context.touches.insert(touch.id, touch);
+        mouse_motion_event(1., 2.);
+        context.input_events.push(MiniquadInputEvent::Touch {
+            phase: touch.phase,
+            id: touch.id,
+            x: touch.x,
+            y: touch.y,
+        });
+    }
+}
+
+/// The `helper()` of the `game_loop` example
+fn helper(gl: &mut InternalGlContext) {
+    *gl.quad_gl() += 1;
+}
+
+/// `helper()` borrowing the caller's handle instead of creating its own
+#[test]
+fn sound_helper_with_outer_internal_gl() {
+    let _context = fresh_context();
+    let mut token = take_gl_token().unwrap();
+    with_internal_gl(&mut token, |mut gl| {
+        gl.flush();
+        *gl.quad_context() += 1;
+        helper(&mut gl);
+        *gl.quad_gl() += 1;
+    });
+
+    assert_eq!(context(|c| (c.quad_context, c.gl)), (2, 3));
+}
+
+/// The original `helper()`, creating its own raw handle
+fn raw_helper() {
+    unsafe {
+        let gl = get_internal_gl();
+        *gl.quad_gl += 1;
+    }
+}
+
+/// The raw handle is fine as long as nothing else touches the `Context`
+/// while its pointers are in use
+#[test]
+fn sound_raw_internal_gl_without_interleaving() {
+    let _context = fresh_context();
+    raw_helper();
+    unsafe {
+        let gl = get_internal_gl();
+        *gl.quad_context += 1;
+        *gl.quad_gl += 1;
+    }
+
+    assert_eq!(context(|c| (c.quad_context, c.gl)), (1, 2));
+}
+
+/// `helper()` creating a second raw handle while `main` holds one.
+///
+/// UB under Stacked Borrows and Tree Borrows: `gl.quad_gl` is written
+/// after `raw_helper()` wrote the same location through another handle.
+#[test]
+#[ignore = "UB: raw_helper() writes quad_gl while the outer handle is live"]
+fn unsound_helper_with_outer_raw_internal_gl() {
+    let _context = fresh_context();
+    unsafe {
+        let gl = get_internal_gl();
+        raw_helper();
+        *gl.quad_gl += 1;
+    }
+}
+
+/// `main`'s original loop: `flush()` goes through the `Context` while
+/// the raw handle is live.
+///
+/// UB under Stacked Borrows and Tree Borrows: `gl.quad_context` is
+/// written after `flush()` wrote it through another reference.
+#[test]
+#[ignore = "UB: flush() writes quad_context while the raw handle is live"]
+fn unsound_raw_internal_gl_across_flush() {
+    let _context = fresh_context();
+    unsafe {
+        let mut gl = get_internal_gl();
+        gl.flush();
+        *gl.quad_context += 1;
+    }
+}
+
+fn sound_data() -> SoundData {
+    SoundData {
+        sample_rate: 8000,
+        channels: 1,
+        samples: vec![0.0, 1.0],
+    }
+}
+
+/// One `&mut Context` split into disjoint field borrows, as the first
+/// `load_sound_from_bytes()` did
+#[test]
+fn sound_split_field_borrows() {
+    let _context = fresh_context();
+    let _mixer = take_audio_mixer().unwrap();
+    let sound = with_context(|context| {
+        let audio_context = &mut context.audio_context;
+        context.mouse_x += 1.0;
+        audio_context.add_sound(sound_data())
+    });
+    let other = load_sound_from_bytes(&wav(1, 1, 8000, 8, &[3])).unwrap();
+
+    assert_ne!(sound, other);
+    assert!(play_sound(sound, PlaySoundParams::default()).is_some());
+    assert_eq!(context(|c| c.mouse_x), 1.);
+}
+
+/// The same split with a second `get_context()` for `mouse_x` while the
+/// `audio_context` borrow is live.
+///
+/// UB under Stacked Borrows only: the second `&mut Context` invalidates
+/// `audio_context`. Tree Borrows accepts it because the accesses are to
+/// disjoint fields.
+#[test]
+#[ignore = "UB (Stacked Borrows): second &mut Context invalidates audio_context"]
+fn unsound_split_borrow_overlapping_get_context() {
+    let _context = fresh_context();
+    unsafe {
+        let audio_context = &mut (*get_context()).audio_context;
+        (*get_context()).mouse_x += 1.0;
+        audio_context.add_sound(sound_data());
+    }
+}
+
+/// The mixer runs on a second thread while the main thread keeps calling
+/// the audio functions: they only share the channels, never the `Context`
+#[test]
+fn sound_mixer_on_audio_thread() {
+    let _context = fresh_context();
+    let mut mixer = take_audio_mixer().unwrap();
+    let sound = load_sound_from_bytes(&wav(1, 1, 8000, 8, &[192; 4])).unwrap();
+    let voice = play_sound(sound, PlaySoundParams::default()).unwrap();
+
+    let audio_thread = std::thread::spawn(move || {
+        let mut buffer = [0.0; 8];
+        mixer.mix(&mut buffer);
+        buffer
+    });
+    stop_sound(sound);
+    let buffer = audio_thread.join().unwrap();
+
+    // the stop was either applied before mixing or not at all
+    assert!(buffer == [0.5; 8] || buffer == [0.0; 8]);
+    assert!(!is_voice_playing(voice));
+}
+
+/// Handles into a reset `Context` are detected instead of dangling
+#[test]
+fn sound_stale_handle_is_detected() {
+    let _context = fresh_context();
+    let mut gl = unsafe { get_internal_gl() };
+    unsafe { reset_context() }.unwrap();
+    assert!(gl.is_stale());
+    assert_eq!(gl.try_flush(), Err(ContextError::StaleHandle));
+}
+
+/// A worker thread polling the `Context` while the main thread creates and
+/// drops it: the worker is turned away in every state, including while no
+/// `Context` exists, so it never reads `CONTEXT` during `init_context()`
+#[test]
+fn sound_worker_during_init_and_shutdown() {
+    let _serial = serial();
+    let worker = std::thread::spawn(|| {
+        for _ in 0..50 {
+            match try_with_context_ref(|context| context.frame) {
+                Err(ContextError::NotInitialized | ContextError::WrongThread { .. }) => {}
+                result => panic!("worker reached the Context: {:?}", result),
+            }
+        }
+    });
+    for _ in 0..20 {
+        unsafe {
+            init_context().unwrap();
+            shutdown_context().unwrap();
+        }
+    }
+    worker.join().unwrap();
+}
+
+/// With the `checked` feature, the raw access is caught at runtime
+#[cfg(feature = "checked")]
+#[test]
+#[should_panic(expected = "RacyCell already mutably borrowed")]
+fn checked_get_context_inside_with_context_panics() {
+    let _context = fresh_context();
+    with_context(|_ctx| {
+        // SAFETY: never dereferenced, the call is expected to panic
+        let _ = unsafe { CONTEXT.get_ref_mut() };
+    });
+}
@@ -0,0 +1,406 @@
+//! Window size and access to the graphics state of the [Context]
+
+use crate::replay::InputCall;
+use crate::{
+    context_generation, get_context, try_with_context, with_context, with_context_ref, Context,
+    ContextError,
+};
+
+/// Access to the graphics state of the `Context`, see [with_internal_gl()]
+///
+/// The handle mutably borrows the [GlToken] it was created with. There is
+/// only one token per `Context`, so holding two handles at the same time is
+/// a compile error:
+///
+/// ```compile_fail,E0499
+/// # use macroquad_ub_test::window::{take_gl_token, with_internal_gl};
+/// let mut token = take_gl_token().unwrap();
+/// with_internal_gl(&mut token, |outer| {
+///     with_internal_gl(&mut token, |inner| {});
+/// });
+/// ```
+pub struct InternalGlContext<'a> {
+    context: &'a mut Context,
+}
+
+impl InternalGlContext<'_> {
+    pub fn quad_context(&mut self) -> &mut u32 {
+        &mut self.context.quad_context
+    }
+
+    pub fn quad_gl(&mut self) -> &mut u32 {
+        &mut self.context.gl
+    }
+
+    pub fn flush(&mut self) {
+        self.context.perform_render_passes();
+    }
+}
+
+/// The right to create [InternalGlContext]s, owned by the main loop. Only
+/// one exists per `Context`, see [take_gl_token()].
+#[derive(Debug)]
+pub struct GlToken {
+    /// Generation of the `Context` the token belongs to
+    generation: u64,
+}
+
+impl GlToken {
+    /// Whether the `Context` this token was taken from has been shut down or
+    /// reset, a new token must be taken then
+    pub fn is_stale(&self) -> bool {
+        self.generation != context_generation()
+    }
+}
+
+/// Take the [GlToken] of the `Context`. Returns `None` if it was already
+/// taken.
+///
+/// # Panics
+/// On any [ContextError]
+#[track_caller]
+pub fn take_gl_token() -> Option<GlToken> {
+    with_context(|context| {
+        if context.gl_token_taken {
+            return None;
+        }
+        context.gl_token_taken = true;
+        Some(GlToken {
+            generation: context_generation(),
+        })
+    })
+}
+
+/// Run `f` with the graphics state of the `Context`.
+///
+/// ```
+/// # use macroquad_ub_test::init_context;
+/// # use macroquad_ub_test::window::{take_gl_token, with_internal_gl};
+/// # unsafe { init_context() }.unwrap();
+/// let mut token = take_gl_token().unwrap();
+/// with_internal_gl(&mut token, |mut gl| gl.flush());
+/// ```
+///
+/// # Panics
+/// - if `token` is stale, see [GlToken::is_stale]
+/// - on any [ContextError]
+#[track_caller]
+pub fn with_internal_gl<R>(token: &mut GlToken, f: impl FnOnce(InternalGlContext) -> R) -> R {
+    match try_with_internal_gl(token, f) {
+        Ok(result) => result,
+        Err(err) => panic!("{}", err),
+    }
+}
+
+/// Fallible version of [with_internal_gl()]
+#[track_caller]
+pub fn try_with_internal_gl<R>(
+    token: &mut GlToken,
+    f: impl FnOnce(InternalGlContext) -> R,
+) -> Result<R, ContextError> {
+    if token.is_stale() {
+        return Err(ContextError::StaleHandle);
+    }
+    try_with_context(|context| f(InternalGlContext { context }))
+}
+
+/// Raw pointer form of [InternalGlContext], kept for code that has not
+/// migrated to [with_internal_gl()] yet.
+///
+/// Nothing prevents the pointers from aliasing other borrows of the
+/// `Context`, see [get_internal_gl()].
+pub struct RawInternalGlContext {
+    pub quad_context: *mut u32,
+    pub quad_gl: *mut u32,
+    /// Generation of the `Context` the pointers point into
+    generation: u64,
+}
+
+impl RawInternalGlContext {
+    /// # Safety
+    /// Requirements:
+    /// - this function must only be called from the "main" thread, which is
+    ///   checked: calls from other threads panic
+    /// - the pointers must only be dereferenced while no other reference to
+    ///   the `Context` is live, i.e. not across calls to other macroquad
+    ///   functions
+    #[track_caller]
+    pub unsafe fn new() -> Self {
+        let context = get_context();
+
+        RawInternalGlContext {
+            quad_context: &mut (*context).quad_context as *mut u32,
+            quad_gl: &mut (*context).gl as *mut u32,
+            generation: context_generation(),
+        }
+    }
+
+    /// Whether the `Context` this handle was created from has been shut down
+    /// or reset. The pointers of a stale handle must not be dereferenced.
+    pub fn is_stale(&self) -> bool {
+        self.generation != context_generation()
+    }
+
+    /// # Panics
+    /// - if the handle is stale, see [RawInternalGlContext::is_stale]
+    /// - if called from another thread than the one that called `init_context()`
+    #[track_caller]
+    pub fn flush(&mut self) {
+        if let Err(err) = self.try_flush() {
+            panic!("{}", err);
+        }
+    }
+
+    /// Fallible version of [RawInternalGlContext::flush]
+    #[track_caller]
+    pub fn try_flush(&mut self) -> Result<(), ContextError> {
+        if self.is_stale() {
+            return Err(ContextError::StaleHandle);
+        }
+        try_with_context(|c| c.perform_render_passes())
+    }
+}
+
+/// Migration shim for [RawInternalGlContext::new], prefer
+/// [with_internal_gl()].
+///
+/// # Safety
+/// See [RawInternalGlContext::new]
+#[track_caller]
+pub unsafe fn get_internal_gl() -> RawInternalGlContext {
+    RawInternalGlContext::new()
+}
+
+/// Limits on the logical window size, see [set_window_constraints()]
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct WindowConstraints {
+    /// Smallest width and height, wins over `max_size`
+    pub min_size: Option<(f32, f32)>,
+    pub max_size: Option<(f32, f32)>,
+    /// Width divided by height. Sizes with another ratio are shrunk to fit
+    /// (and grown again if that breaks `min_size`).
+    pub aspect_ratio: Option<f32>,
+}
+
+impl WindowConstraints {
+    /// Whether the sizes are finite and not negative, and the ratio finite
+    /// and positive
+    pub fn is_valid(&self) -> bool {
+        let valid_size = |size: Option<(f32, f32)>| {
+            size.is_none_or(|(width, height)| {
+                width.is_finite() && height.is_finite() && width >= 0.0 && height >= 0.0
+            })
+        };
+        valid_size(self.min_size)
+            && valid_size(self.max_size)
+            && self
+                .aspect_ratio
+                .is_none_or(|ratio| ratio.is_finite() && ratio > 0.0)
+    }
+
+    /// Constrain a logical size, only meaningful for
+    /// [valid](WindowConstraints::is_valid) constraints
+    pub fn apply(&self, width: f32, height: f32) -> (f32, f32) {
+        let (mut width, mut height) = (width, height);
+        if let Some((max_width, max_height)) = self.max_size {
+            width = width.min(max_width);
+            height = height.min(max_height);
+        }
+        if let Some(ratio) = self.aspect_ratio {
+            if width > height * ratio {
+                width = height * ratio;
+            } else {
+                height = width / ratio;
+            }
+        }
+        if let Some((min_width, min_height)) = self.min_size {
+            if let Some(ratio) = self.aspect_ratio {
+                if width <= 0.0 || height <= 0.0 {
+                    // nothing to scale, as for minimized windows reported
+                    // as 0x0: the smallest size with the ratio
+                    width = min_width.max(min_height * ratio);
+                    height = width / ratio;
+                } else {
+                    // grow both sides to keep the ratio
+                    let scale = (min_width / width).max(min_height / height).max(1.0);
+                    width *= scale;
+                    height *= scale;
+                }
+            } else {
+                width = width.max(min_width);
+                height = height.max(min_height);
+            }
+        }
+        (width, height)
+    }
+}
+
+/// Limit the logical window size, enforced by
+/// [resize_event()](crate::resize_event). The current size is constrained
+/// right away.
+///
+/// # Panics
+/// - if the constraints are not [valid](WindowConstraints::is_valid)
+/// - on any [ContextError]
+#[track_caller]
+pub fn set_window_constraints(constraints: WindowConstraints) {
+    assert!(
+        constraints.is_valid(),
+        "invalid window constraints {:?}",
+        constraints
+    );
+    with_context(|context| {
+        context.record(InputCall::SetWindowConstraints { constraints });
+        context.window_constraints = constraints;
+        context.update_screen_size();
+    });
+}
+
+/// # Panics
+/// On any [ContextError]
+#[track_caller]
+pub fn window_constraints() -> WindowConstraints {
+    with_context_ref(|context| context.window_constraints)
+}
+
+/// Physical pixels per logical pixel.
+///
+/// # Panics
+/// On any [ContextError]
+#[track_caller]
+pub fn dpi_scale() -> f32 {
+    with_context_ref(|context| context.dpi_scale)
+}
+
+/// Window width in logical pixels, see [logical_screen_size()].
+///
+/// # Panics
+/// On any [ContextError]
+#[track_caller]
+pub fn screen_width() -> f32 {
+    with_context_ref(|context| context.screen_width)
+}
+
+/// Window height in logical pixels, see [logical_screen_size()].
+///
+/// # Panics
+/// On any [ContextError]
+#[track_caller]
+pub fn screen_height() -> f32 {
+    with_context_ref(|context| context.screen_height)
+}
+
+/// Window size in logical pixels, after applying the [WindowConstraints].
+///
+/// # Panics
+/// On any [ContextError]
+#[track_caller]
+pub fn logical_screen_size() -> (f32, f32) {
+    with_context_ref(|context| (context.screen_width, context.screen_height))
+}
+
+/// Window size in physical pixels, after applying the [WindowConstraints].
+///
+/// # Panics
+/// On any [ContextError]
+#[track_caller]
+pub fn physical_screen_size() -> (f32, f32) {
+    with_context_ref(|context| {
+        (
+            context.screen_width * context.dpi_scale,
+            context.screen_height * context.dpi_scale,
+        )
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::fresh_context;
+    use crate::{dpi_scale_event, resize_event};
+
+    #[test]
+    fn one_gl_token_per_context() {
+        let _context = fresh_context();
+        let mut token = take_gl_token().unwrap();
+        assert!(take_gl_token().is_none());
+        with_internal_gl(&mut token, |mut gl| *gl.quad_gl() += 1);
+
+        unsafe { crate::reset_context() }.unwrap();
+        assert!(token.is_stale());
+        assert_eq!(
+            try_with_internal_gl(&mut token, |_| ()).unwrap_err(),
+            ContextError::StaleHandle
+        );
+        let mut token = take_gl_token().unwrap();
+        assert_eq!(try_with_internal_gl(&mut token, |_| ()), Ok(()));
+    }
+
+    #[test]
+    fn logical_size_follows_dpi_scale() {
+        let _context = fresh_context();
+        resize_event(1920., 1080.);
+        dpi_scale_event(2.);
+        assert_eq!(logical_screen_size(), (960., 540.));
+        assert_eq!(physical_screen_size(), (1920., 1080.));
+    }
+
+    #[test]
+    #[should_panic(expected = "DPI scale must be finite and positive, got 0")]
+    fn zero_dpi_scale_is_rejected() {
+        let _context = fresh_context();
+        dpi_scale_event(0.);
+    }
+
+    #[test]
+    fn invalid_constraints_are_rejected() {
+        let invalid = [
+            (None, Some(0.)),
+            (None, Some(f32::NAN)),
+            (None, Some(-1.)),
+            (Some((f32::INFINITY, 1.)), None),
+            (Some((-1., 1.)), None),
+        ];
+        for (min_size, aspect_ratio) in invalid {
+            let constraints = WindowConstraints {
+                min_size,
+                aspect_ratio,
+                ..WindowConstraints::default()
+            };
+            assert!(!constraints.is_valid(), "{:?}", constraints);
+        }
+
+        let _context = fresh_context();
+        resize_event(1920., 1080.);
+        let result = std::panic::catch_unwind(|| {
+            set_window_constraints(WindowConstraints {
+                aspect_ratio: Some(0.),
+                ..WindowConstraints::default()
+            })
+        });
+        assert!(result.is_err());
+        assert_eq!(logical_screen_size(), (1920., 1080.));
+    }
+
+    #[test]
+    fn constraints() {
+        let constraints = WindowConstraints {
+            min_size: Some((400., 300.)),
+            max_size: Some((1600., 1200.)),
+            aspect_ratio: Some(4. / 3.),
+        };
+        assert_eq!(constraints.apply(1920., 1080.), (1440., 1080.));
+        assert_eq!(constraints.apply(4000., 4000.), (1600., 1200.));
+        assert_eq!(constraints.apply(200., 600.), (400., 300.));
+        // minimized
+        assert_eq!(constraints.apply(0., 0.), (400., 300.));
+        assert_eq!(constraints.apply(0., 600.), (400., 300.));
+
+        let _context = fresh_context();
+        resize_event(1920., 1080.);
+        set_window_constraints(constraints);
+        assert_eq!(logical_screen_size(), (1440., 1080.));
+        resize_event(0., 0.);
+        assert_eq!(logical_screen_size(), (400., 300.));
+    }
+}